@@ -0,0 +1,2 @@
+ALTER TABLE posts DROP COLUMN slug;
+ALTER TABLE post_revisions DROP COLUMN slug;
@@ -0,0 +1,3 @@
+-- Posts get a URL slug, kept from imported sites or made from the title
+ALTER TABLE posts ADD COLUMN slug VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE post_revisions ADD COLUMN slug VARCHAR NOT NULL DEFAULT '';
@@ -0,0 +1,44 @@
+-- Back to the VARCHAR JSON node references
+CREATE OR REPLACE FUNCTION pg_temp.n4_reference(content_type VARCHAR, id INTEGER) RETURNS VARCHAR AS $$
+    SELECT json_build_object(
+        'content_type', CASE content_type WHEN 'none' THEN 'None' ELSE content_type END,
+        'id', COALESCE(id, 0)
+    )::TEXT
+$$ LANGUAGE SQL IMMUTABLE;
+
+ALTER TABLE link_revisions DROP CONSTRAINT link_revisions_parent_fkey;
+ALTER TABLE link_revisions ALTER COLUMN parent TYPE VARCHAR (255) USING pg_temp.n4_reference('None', parent);
+ALTER TABLE link_revisions ALTER COLUMN parent SET NOT NULL;
+ALTER TABLE link_revisions ALTER COLUMN parent SET DEFAULT '{"content_type": "None", "id": 0}';
+
+ALTER TABLE post_revisions DROP CONSTRAINT post_revisions_parent_fkey;
+ALTER TABLE post_revisions ALTER COLUMN parent TYPE VARCHAR (255) USING pg_temp.n4_reference('None', parent);
+ALTER TABLE post_revisions ALTER COLUMN parent SET NOT NULL;
+ALTER TABLE post_revisions ALTER COLUMN parent SET DEFAULT '{"content_type": "None", "id": 0}';
+
+ALTER TABLE links DROP CONSTRAINT links_parent_fkey;
+ALTER TABLE links ALTER COLUMN parent TYPE VARCHAR (255) USING pg_temp.n4_reference('None', parent);
+ALTER TABLE links ALTER COLUMN parent SET NOT NULL;
+ALTER TABLE links ALTER COLUMN parent SET DEFAULT '{"content_type": "None", "id": 0}';
+
+ALTER TABLE posts DROP CONSTRAINT posts_parent_fkey;
+ALTER TABLE posts ALTER COLUMN parent TYPE VARCHAR (255) USING pg_temp.n4_reference('None', parent);
+ALTER TABLE posts ALTER COLUMN parent SET NOT NULL;
+ALTER TABLE posts ALTER COLUMN parent SET DEFAULT '{"content_type": "None", "id": 0}';
+
+DROP TRIGGER check_node_child ON nodes;
+DROP FUNCTION n4_check_node_child();
+
+ALTER TABLE node_revisions DROP CONSTRAINT node_revisions_child_check;
+ALTER TABLE node_revisions DROP CONSTRAINT node_revisions_child_content_type_check;
+ALTER TABLE node_revisions ALTER COLUMN child TYPE VARCHAR (255) USING pg_temp.n4_reference(child_content_type, child);
+ALTER TABLE node_revisions ALTER COLUMN child SET NOT NULL;
+ALTER TABLE node_revisions ALTER COLUMN child SET DEFAULT '{"content_type": "None", "id": 0}';
+ALTER TABLE node_revisions DROP COLUMN child_content_type;
+
+ALTER TABLE nodes DROP CONSTRAINT nodes_child_check;
+ALTER TABLE nodes DROP CONSTRAINT nodes_child_content_type_check;
+ALTER TABLE nodes ALTER COLUMN child TYPE VARCHAR (255) USING pg_temp.n4_reference(child_content_type, child);
+ALTER TABLE nodes ALTER COLUMN child SET NOT NULL;
+ALTER TABLE nodes ALTER COLUMN child SET DEFAULT '{"content_type": "None", "id": 0}';
+ALTER TABLE nodes DROP COLUMN child_content_type;
@@ -0,0 +1,92 @@
+-- The node references were created as VARCHAR columns holding JSON like
+-- {"content_type": "None", "id": 0}, turn them into integer foreign keys (NULL for no node).
+
+-- Reads the id out of the old JSON values, or a plain number
+CREATE OR REPLACE FUNCTION pg_temp.n4_reference_id(reference VARCHAR) RETURNS INTEGER AS $$
+    SELECT NULLIF(CASE
+        WHEN reference ~ '^\s*\d+\s*$' THEN reference::INTEGER
+        WHEN reference ~ '^\s*\{' THEN (reference::JSON->>'id')::INTEGER
+    END, 0)
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION pg_temp.n4_reference_type(reference VARCHAR) RETURNS VARCHAR AS $$
+    SELECT CASE
+        WHEN reference ~ '^\s*\{' AND lower(reference::JSON->>'content_type') IN ('post', 'link')
+            THEN lower(reference::JSON->>'content_type')
+        ELSE 'none'
+    END
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- Nodes point at their content with a child id and the content type it's in
+ALTER TABLE nodes ADD COLUMN child_content_type VARCHAR (16) NOT NULL DEFAULT 'none';
+UPDATE nodes SET child_content_type = pg_temp.n4_reference_type(child);
+ALTER TABLE nodes ALTER COLUMN child DROP DEFAULT;
+ALTER TABLE nodes ALTER COLUMN child DROP NOT NULL;
+ALTER TABLE nodes ALTER COLUMN child TYPE INTEGER USING pg_temp.n4_reference_id(child);
+UPDATE nodes SET child = NULL, child_content_type = 'none'
+    WHERE child IS NULL
+    OR (child_content_type = 'post' AND child NOT IN (SELECT id FROM posts))
+    OR (child_content_type = 'link' AND child NOT IN (SELECT id FROM links))
+    OR child_content_type = 'none';
+ALTER TABLE nodes ADD CONSTRAINT nodes_child_content_type_check
+    CHECK (child_content_type IN ('none', 'post', 'link'));
+ALTER TABLE nodes ADD CONSTRAINT nodes_child_check
+    CHECK ((child_content_type = 'none') = (child IS NULL));
+
+ALTER TABLE node_revisions ADD COLUMN child_content_type VARCHAR (16) NOT NULL DEFAULT 'none';
+UPDATE node_revisions SET child_content_type = pg_temp.n4_reference_type(child);
+ALTER TABLE node_revisions ALTER COLUMN child DROP DEFAULT;
+ALTER TABLE node_revisions ALTER COLUMN child DROP NOT NULL;
+ALTER TABLE node_revisions ALTER COLUMN child TYPE INTEGER USING pg_temp.n4_reference_id(child);
+UPDATE node_revisions SET child = NULL, child_content_type = 'none'
+    WHERE child IS NULL OR child_content_type = 'none';
+ALTER TABLE node_revisions ADD CONSTRAINT node_revisions_child_content_type_check
+    CHECK (child_content_type IN ('none', 'post', 'link'));
+ALTER TABLE node_revisions ADD CONSTRAINT node_revisions_child_check
+    CHECK ((child_content_type = 'none') = (child IS NULL));
+
+-- The child of a node can be in either content table, a trigger does the foreign key check
+CREATE FUNCTION n4_check_node_child() RETURNS trigger AS $$
+BEGIN
+    IF NEW.child IS NULL THEN
+        -- Left to the nodes_child_check constraint
+    ELSIF NEW.child_content_type = 'post' AND NOT EXISTS (SELECT 1 FROM posts WHERE id = NEW.child) THEN
+        RAISE foreign_key_violation USING MESSAGE = format('node %s child post %s does not exist', NEW.id, NEW.child);
+    ELSIF NEW.child_content_type = 'link' AND NOT EXISTS (SELECT 1 FROM links WHERE id = NEW.child) THEN
+        RAISE foreign_key_violation USING MESSAGE = format('node %s child link %s does not exist', NEW.id, NEW.child);
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER check_node_child BEFORE INSERT OR UPDATE OF child, child_content_type ON nodes
+    FOR EACH ROW EXECUTE PROCEDURE n4_check_node_child();
+
+-- Content and revisions point at their node
+ALTER TABLE posts ALTER COLUMN parent DROP DEFAULT;
+ALTER TABLE posts ALTER COLUMN parent DROP NOT NULL;
+ALTER TABLE posts ALTER COLUMN parent TYPE INTEGER USING pg_temp.n4_reference_id(parent);
+UPDATE posts SET parent = NULL WHERE parent NOT IN (SELECT id FROM nodes);
+ALTER TABLE posts ADD CONSTRAINT posts_parent_fkey
+    FOREIGN KEY (parent) REFERENCES nodes (id) ON DELETE SET NULL;
+
+ALTER TABLE links ALTER COLUMN parent DROP DEFAULT;
+ALTER TABLE links ALTER COLUMN parent DROP NOT NULL;
+ALTER TABLE links ALTER COLUMN parent TYPE INTEGER USING pg_temp.n4_reference_id(parent);
+UPDATE links SET parent = NULL WHERE parent NOT IN (SELECT id FROM nodes);
+ALTER TABLE links ADD CONSTRAINT links_parent_fkey
+    FOREIGN KEY (parent) REFERENCES nodes (id) ON DELETE SET NULL;
+
+ALTER TABLE post_revisions ALTER COLUMN parent DROP DEFAULT;
+ALTER TABLE post_revisions ALTER COLUMN parent DROP NOT NULL;
+ALTER TABLE post_revisions ALTER COLUMN parent TYPE INTEGER USING pg_temp.n4_reference_id(parent);
+UPDATE post_revisions SET parent = NULL WHERE parent NOT IN (SELECT id FROM nodes);
+ALTER TABLE post_revisions ADD CONSTRAINT post_revisions_parent_fkey
+    FOREIGN KEY (parent) REFERENCES nodes (id) ON DELETE SET NULL;
+
+ALTER TABLE link_revisions ALTER COLUMN parent DROP DEFAULT;
+ALTER TABLE link_revisions ALTER COLUMN parent DROP NOT NULL;
+ALTER TABLE link_revisions ALTER COLUMN parent TYPE INTEGER USING pg_temp.n4_reference_id(parent);
+UPDATE link_revisions SET parent = NULL WHERE parent NOT IN (SELECT id FROM nodes);
+ALTER TABLE link_revisions ADD CONSTRAINT link_revisions_parent_fkey
+    FOREIGN KEY (parent) REFERENCES nodes (id) ON DELETE SET NULL;
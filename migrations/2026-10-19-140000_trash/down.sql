@@ -0,0 +1,4 @@
+-- Content still in the trash comes back
+ALTER TABLE posts DROP COLUMN trashed;
+ALTER TABLE links DROP COLUMN trashed;
+ALTER TABLE system DROP COLUMN trashed;
@@ -0,0 +1,4 @@
+-- Deleted content goes to the trash first, it's kept (with its revisions) until purged
+ALTER TABLE posts ADD COLUMN trashed TIMESTAMP WITH TIME ZONE;
+ALTER TABLE links ADD COLUMN trashed TIMESTAMP WITH TIME ZONE;
+ALTER TABLE system ADD COLUMN trashed TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,9 @@
+-- The last check of every URL in the links and post bodies, by URL so a URL used in several places
+-- is checked once
+CREATE TABLE url_checks (
+  url VARCHAR PRIMARY KEY,
+  status INTEGER,
+  redirect VARCHAR,
+  error VARCHAR,
+  checked TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
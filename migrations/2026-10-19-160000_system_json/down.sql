@@ -0,0 +1,6 @@
+DROP TABLE system_schemas;
+
+ALTER TABLE system ALTER COLUMN data TYPE VARCHAR
+  USING CASE WHEN jsonb_typeof(data) = 'string' THEN data #>> '{}' ELSE data::TEXT END;
+ALTER TABLE system_revisions ALTER COLUMN data TYPE VARCHAR
+  USING CASE WHEN jsonb_typeof(data) = 'string' THEN data #>> '{}' ELSE data::TEXT END;
@@ -0,0 +1,27 @@
+-- System values are JSON.  Text that is a JSON object or array is kept as that JSON, any other text
+-- becomes a JSON string as it is, so "42", "true", "null" or "\"quoted\"" stay the strings heads
+-- read before and the down migration gives them back unchanged
+CREATE FUNCTION pg_temp.text_to_jsonb(value TEXT) RETURNS JSONB AS $$
+DECLARE
+  parsed JSONB;
+BEGIN
+  parsed := value::JSONB;
+  IF jsonb_typeof(parsed) IN ('object', 'array') THEN
+    RETURN parsed;
+  END IF;
+  RETURN to_jsonb(value);
+EXCEPTION WHEN others THEN
+  RETURN to_jsonb(value);
+END;
+$$ LANGUAGE plpgsql IMMUTABLE;
+
+ALTER TABLE system ALTER COLUMN data TYPE JSONB USING pg_temp.text_to_jsonb(data);
+ALTER TABLE system_revisions ALTER COLUMN data TYPE JSONB USING pg_temp.text_to_jsonb(data);
+
+-- The JSON Schema the value of a system key has to match, kept apart from the entry so a schema can
+-- be set up before the value
+CREATE TABLE system_schemas (
+  key VARCHAR PRIMARY KEY NOT NULL,
+  schema JSONB NOT NULL,
+  updated TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
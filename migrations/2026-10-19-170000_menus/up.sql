@@ -0,0 +1,25 @@
+-- Named navigation menus, like "main" or "footer"
+CREATE TABLE menus (
+  name VARCHAR PRIMARY KEY NOT NULL,
+  title VARCHAR NOT NULL DEFAULT '',
+  time TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  updated TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+-- The items of a menu, ordered by position under their parent item (NULL for the top level).  An
+-- item points at a node, a post by its slug or a URL, or at nothing as a heading for the items
+-- under it.  The node has no foreign key, an item whose node was purged keeps pointing at it so
+-- it's flagged as missing instead of turning into a heading.
+CREATE TABLE menu_items (
+  id SERIAL PRIMARY KEY,
+  menu VARCHAR NOT NULL REFERENCES menus (name) ON DELETE CASCADE,
+  parent INTEGER REFERENCES menu_items (id) ON DELETE CASCADE,
+  position INTEGER NOT NULL DEFAULT 0,
+  label VARCHAR NOT NULL,
+  node INTEGER,
+  post_slug VARCHAR,
+  url VARCHAR,
+  CONSTRAINT menu_items_target_check CHECK (num_nonnulls(node, post_slug, url) <= 1)
+);
+
+CREATE INDEX menu_items_menu_idx ON menu_items (menu, parent, position);
@@ -0,0 +1,3 @@
+DROP TRIGGER delete_link_nodes ON links;
+DROP TRIGGER delete_post_nodes ON posts;
+DROP FUNCTION n4_delete_child_nodes();
@@ -0,0 +1,25 @@
+-- The check_node_child trigger only covers changes to nodes, deleting a post or link left its node
+-- pointing at nothing.  Now the node (and its revisions) go with the content, like purging the
+-- trash does.
+CREATE FUNCTION n4_delete_child_nodes() RETURNS trigger AS $$
+BEGIN
+    DELETE FROM node_revisions WHERE id IN (
+        SELECT id FROM nodes WHERE child = OLD.id AND child_content_type = TG_ARGV[0]
+    );
+    DELETE FROM nodes WHERE child = OLD.id AND child_content_type = TG_ARGV[0];
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER delete_post_nodes AFTER DELETE ON posts
+    FOR EACH ROW EXECUTE PROCEDURE n4_delete_child_nodes('post');
+CREATE TRIGGER delete_link_nodes AFTER DELETE ON links
+    FOR EACH ROW EXECUTE PROCEDURE n4_delete_child_nodes('link');
+
+-- Nodes already left pointing at deleted content
+CREATE TEMPORARY TABLE n4_dangling_nodes AS SELECT id FROM nodes
+    WHERE (child_content_type = 'post' AND child NOT IN (SELECT id FROM posts))
+    OR (child_content_type = 'link' AND child NOT IN (SELECT id FROM links));
+DELETE FROM node_revisions WHERE id IN (SELECT id FROM n4_dangling_nodes);
+DELETE FROM nodes WHERE id IN (SELECT id FROM n4_dangling_nodes);
+DROP TABLE n4_dangling_nodes;
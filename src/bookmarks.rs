@@ -0,0 +1,350 @@
+// Browser bookmarks in the Netscape bookmark file format, the HTML every browser exports and
+// imports.  Folders are <H3> headings followed by a <DL> list, bookmarks are <A> tags with an
+// optional <DD> description after them.  On import the folders a bookmark is in become its tags,
+// on export every link goes in the folder of its first tag with all its tags in a TAGS attribute,
+// so exporting and importing again keeps the tags.
+
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use diesel::prelude::*;
+use diesel::result::Error::RollbackTransaction;
+use crate::models::Link;
+use crate::pageinfo::{decode_entities, read_tag};
+use crate::schema::links;
+use crate::{shared_connection, split_tags};
+use crate::tags::normalize_tags;
+
+/// A bookmark from a bookmark file, not yet saved.
+#[derive(Debug)]
+pub struct Bookmark {
+    pub text: String,
+    /// The description, or the text when there isn't one
+    pub title: String,
+    pub url: String,
+    pub tags: String,
+    /// When it was bookmarked (UTC)
+    pub time: Option<NaiveDateTime>,
+}
+
+/// A bookmark that isn't imported, with the reason it was left out.
+#[derive(Debug)]
+pub struct BookmarkSkipped {
+    pub text: String,
+    pub reason: String,
+}
+
+/// Parse the bookmarks out of a Netscape bookmark file.
+///
+/// Every folder a bookmark is in becomes a tag, along with the tags in its TAGS attribute
+/// (Firefox), except the browser's own toolbar and unsorted folders.  A URL bookmarked more than
+/// once is one bookmark with the tags of every copy.  Bookmarks that aren't http or https URLs
+/// (bookmarklets, Firefox smart folders) are returned as skipped.
+///
+/// ```
+/// use nautilus::bookmarks::parse_bookmarks;
+///
+/// let html = r#"<!DOCTYPE NETSCAPE-Bookmark-file-1>
+/// <DL><p>
+///   <DT><H3 PERSONAL_TOOLBAR_FOLDER="true">Bookmarks bar</H3>
+///   <DL><p>
+///     <DT><H3>Rust</H3>
+///     <DL><p>
+///       <DT><A HREF="https://www.rust-lang.org/" ADD_DATE="1587762543" TAGS="lang">Rust &amp; Co</A>
+///       <DD>The Rust home page
+///       <DT><A HREF="javascript:alert(1)">Bookmarklet</A>
+///     </DL><p>
+///     <DT><A HREF="https://crates.io/">crates.io</A>
+///   </DL><p>
+///   <DT><H3>Packages</H3>
+///   <DL><p>
+///     <DT><A HREF="https://crates.io/">Crates</A>
+///   </DL><p>
+/// </DL><p>"#;
+/// let (bookmarks, skipped) = parse_bookmarks(html);
+/// assert_eq!(bookmarks.len(), 2);
+/// assert_eq!(bookmarks[0].text, "Rust & Co");
+/// assert_eq!(bookmarks[0].title, "The Rust home page");
+/// assert_eq!(bookmarks[0].tags, "rust, lang");
+/// assert_eq!(bookmarks[0].time.unwrap().to_string(), "2020-04-24 21:09:03");
+/// assert_eq!(bookmarks[1].url, "https://crates.io/");
+/// assert_eq!(bookmarks[1].tags, "packages");
+/// assert_eq!(skipped[0].text, "Bookmarklet");
+/// ```
+pub fn parse_bookmarks(html: &str) -> (Vec<Bookmark>, Vec<BookmarkSkipped>) {
+    let mut bookmarks: Vec<Bookmark> = Vec::new();
+    let mut skipped = Vec::new();
+    // The folder of every open <DL>, None for a browser folder that isn't a tag
+    let mut folders: Vec<Option<String>> = Vec::new();
+    let mut heading: Option<Option<String>> = None;
+    // Where the last bookmark went, for a <DD> description after it
+    let mut last_bookmark: Option<usize> = None;
+    let lowercase = html.to_ascii_lowercase();
+
+    let mut position = 0;
+    while let Some(tag_start) = lowercase[position..].find('<').map(|found| position + found) {
+        let (name, attributes, tag_end) = read_tag(html, tag_start);
+        position = tag_end;
+        let attribute = |wanted: &str| attributes.iter()
+            .find(|(attribute_name, _)| attribute_name == wanted)
+            .map(|(_, value)| decode_entities(value.trim()))
+            .filter(|value| !value.is_empty());
+        let text_until = |closing: &str| lowercase[tag_end..].find(closing)
+            .map(|found| tag_end + found)
+            .unwrap_or(html.len());
+        match name.as_str() {
+            "h3" => {
+                let heading_end = text_until("</h3");
+                let browser_folder = attribute("personal_toolbar_folder").is_some()
+                    || attribute("unfiled_bookmarks_folder").is_some();
+                heading = Some(Some(collapse(&html[tag_end..heading_end])).filter(|_| !browser_folder));
+                position = heading_end;
+                last_bookmark = None;
+            }
+            "dl" => folders.push(heading.take().flatten()),
+            "/dl" => {
+                folders.pop();
+                last_bookmark = None;
+            }
+            "a" => {
+                let text_end = text_until("</a");
+                let url = attribute("href").unwrap_or_default();
+                let text = Some(collapse(&html[tag_end..text_end]))
+                    .filter(|text| !text.is_empty())
+                    .unwrap_or_else(|| url.clone());
+                position = text_end;
+                last_bookmark = None;
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    skipped.push(BookmarkSkipped { text, reason: String::from("not an http or https URL") });
+                    continue;
+                }
+                let mut tags: Vec<String> = folders.iter().flatten().cloned().collect();
+                tags.extend(attribute("tags"));
+                let tags = normalize_tags(&tags.join(","));
+                match bookmarks.iter().position(|bookmark| normalize_url(&bookmark.url) == normalize_url(&url)) {
+                    Some(earlier) => {
+                        let bookmark = &mut bookmarks[earlier];
+                        bookmark.tags = normalize_tags(&format!("{},{}", bookmark.tags, tags));
+                    }
+                    None => {
+                        let time = attribute("add_date")
+                            .and_then(|add_date| add_date.parse::<i64>().ok())
+                            .and_then(|add_date| Utc.timestamp_opt(add_date, 0).single())
+                            .map(|add_date| add_date.naive_utc());
+                        bookmarks.push(Bookmark { title: text.clone(), text, url, tags, time });
+                        last_bookmark = Some(bookmarks.len() - 1);
+                    }
+                }
+            }
+            "dd" => {
+                if let Some(described) = last_bookmark.take() {
+                    let description_end = lowercase[tag_end..].find('<').map(|found| tag_end + found).unwrap_or(html.len());
+                    let description = collapse(&html[tag_end..description_end]);
+                    if !description.is_empty() {
+                        bookmarks[described].title = description;
+                    }
+                    position = description_end;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    (bookmarks, skipped)
+}
+
+/// A URL in the form bookmarks are compared in, with the scheme and host lowercase and without
+/// an empty fragment or a trailing slash.
+///
+/// ```
+/// use nautilus::bookmarks::normalize_url;
+///
+/// assert_eq!(normalize_url("HTTPS://Crates.io/"), "https://crates.io");
+/// assert_eq!(normalize_url(" https://crates.io/crates/diesel/# "), "https://crates.io/crates/diesel");
+/// assert_eq!(normalize_url("https://crates.io/Diesel?q=A"), "https://crates.io/Diesel?q=A");
+/// ```
+pub fn normalize_url(url: &str) -> String {
+    let url = url.trim().trim_end_matches('#').trim_end_matches('/');
+    let host_end = url.find("://")
+        .map(|scheme_end| scheme_end + 3)
+        .map(|host_start| url[host_start..].find(&['/', '?', '#'][..]).map(|found| host_start + found).unwrap_or(url.len()))
+        .unwrap_or(0);
+    format!("{}{}", url[..host_end].to_lowercase(), &url[host_end..])
+}
+
+/// Leave out the bookmarks whose URL is already a link, they're returned as skipped with the id
+/// of the link.  Pass the links in the trash too, a bookmark of one is skipped as in the trash
+/// rather than saved again.
+///
+/// ```
+/// use chrono::NaiveDate;
+/// use nautilus::bookmarks::{parse_bookmarks, skip_existing_links};
+/// use nautilus::models::Link;
+///
+/// let html = r#"<DL><p>
+///   <DT><A HREF="https://crates.io">crates.io</A>
+///   <DT><A HREF="https://docs.rs/#">Docs</A>
+///   <DT><A HREF="https://www.rust-lang.org/">Rust</A>
+/// </DL><p>"#;
+/// let time = NaiveDate::from_ymd(2020, 4, 24).and_hms(0, 0, 0);
+/// let link = |id: i32, url: &str, trashed| Link {
+///     id, text: String::new(), title: String::new(), url: String::from(url), tags: String::new(),
+///     time, version: 1, updated: time, parent: None, trashed,
+/// };
+/// let links = vec![link(1, "https://crates.io/", None), link(2, "https://docs.rs", Some(time))];
+/// let (bookmarks, skipped) = skip_existing_links(parse_bookmarks(html).0, &links);
+/// assert_eq!(bookmarks.len(), 1);
+/// assert_eq!(bookmarks[0].text, "Rust");
+/// assert_eq!(skipped[0].reason, "already link 1");
+/// assert_eq!(skipped[1].reason, "already link 2, in the trash");
+/// ```
+pub fn skip_existing_links(bookmarks: Vec<Bookmark>, links: &[Link]) -> (Vec<Bookmark>, Vec<BookmarkSkipped>) {
+    let mut kept = Vec::new();
+    let mut skipped = Vec::new();
+    for bookmark in bookmarks {
+        let url = normalize_url(&bookmark.url);
+        match links.iter().find(|link| normalize_url(&link.url) == url) {
+            Some(link) if link.trashed.is_some() => skipped.push(BookmarkSkipped {
+                text: bookmark.text,
+                reason: format!("already link {}, in the trash", link.id),
+            }),
+            Some(link) => skipped.push(BookmarkSkipped {
+                text: bookmark.text,
+                reason: format!("already link {}", link.id),
+            }),
+            None => kept.push(bookmark),
+        }
+    }
+    (kept, skipped)
+}
+
+/// Save the bookmarks as new links in one transaction, all of them or none.  A dry run saves them
+/// and rolls back, so it fails wherever the real import would.
+///
+/// ```
+/// use nautilus::bookmarks::{parse_bookmarks, save_bookmarks};
+///
+/// fn import_some_bookmarks(html: &str) {
+///   let (bookmarks, _) = parse_bookmarks(html);
+///   for link in save_bookmarks(&bookmarks, false).unwrap() {
+///     println!("Saved {} with id {}", link.text, link.id);
+///   }
+/// }
+/// ```
+pub fn save_bookmarks(bookmarks: &[Bookmark], dry_run: bool) -> QueryResult<Vec<Link>> {
+    let connection = shared_connection();
+
+    let mut saved = Vec::new();
+    let result = connection.transaction::<_, diesel::result::Error, _>(|| {
+        for bookmark in bookmarks {
+            let link = diesel::insert_into(links::table)
+                .values((
+                    links::text.eq(&bookmark.text),
+                    links::title.eq(&bookmark.title),
+                    links::url.eq(&bookmark.url),
+                    links::tags.eq(&bookmark.tags),
+                    bookmark.time.map(|time| links::time.eq(time)),
+                    bookmark.time.map(|time| links::updated.eq(time)),
+                ))
+                .get_result::<Link>(&*connection)?;
+            saved.push(link);
+        }
+        if dry_run {
+            Err(RollbackTransaction)
+        } else {
+            Ok(())
+        }
+    });
+    match result {
+        Ok(()) => Ok(saved),
+        Err(RollbackTransaction) if dry_run => Ok(saved),
+        Err(e) => Err(e),
+    }
+}
+
+/// Write links as a Netscape bookmark file.  Every link goes in a folder named after its first
+/// tag, untagged links come first outside any folder.
+///
+/// ```
+/// use nautilus::bookmarks::{parse_bookmarks, render_bookmarks};
+/// use nautilus::models::Link;
+/// use chrono::{TimeZone, Utc};
+///
+/// let time = Utc.timestamp_opt(1587762543, 0).unwrap().naive_utc();
+/// let link = Link {
+///   id: 1,
+///   text: String::from("Rust & Co"),
+///   title: String::from("The Rust home page"),
+///   url: String::from("https://www.rust-lang.org/"),
+///   tags: String::from("rust, lang"),
+///   time,
+///   version: 1,
+///   updated: time,
+///   parent: None,
+///   trashed: None,
+/// };
+/// let html = render_bookmarks(&[link]);
+/// assert!(html.contains(r#"<DT><H3>rust</H3>"#));
+/// assert!(html.contains(r#"<DT><A HREF="https://www.rust-lang.org/" ADD_DATE="1587762543" LAST_MODIFIED="1587762543" TAGS="rust,lang">Rust &amp; Co</A>"#));
+///
+/// let (bookmarks, _) = parse_bookmarks(&html);
+/// assert_eq!(bookmarks[0].text, "Rust & Co");
+/// assert_eq!(bookmarks[0].title, "The Rust home page");
+/// assert_eq!(bookmarks[0].tags, "rust, lang");
+/// assert_eq!(bookmarks[0].time, Some(time));
+/// ```
+pub fn render_bookmarks(links: &[Link]) -> String {
+    let mut folders: Vec<(&str, Vec<&Link>)> = vec![("", Vec::new())];
+    for link in links {
+        let folder = split_tags(&link.tags).into_iter().next().unwrap_or("");
+        match folders.iter_mut().find(|(name, _)| *name == folder) {
+            Some((_, folder_links)) => folder_links.push(link),
+            None => folders.push((folder, vec![link])),
+        }
+    }
+
+    let mut html = String::from(
+        "<!DOCTYPE NETSCAPE-Bookmark-file-1>\n\
+         <!-- This is an automatically generated file.\n     It will be read and overwritten.\n     DO NOT EDIT! -->\n\
+         <META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=UTF-8\">\n\
+         <TITLE>Bookmarks</TITLE>\n\
+         <H1>Bookmarks</H1>\n\
+         <DL><p>\n"
+    );
+    for (folder, folder_links) in folders {
+        let indent = if folder.is_empty() { "    " } else { "        " };
+        if !folder.is_empty() {
+            html.push_str(&format!("    <DT><H3>{}</H3>\n    <DL><p>\n", escape(folder)));
+        }
+        for link in folder_links {
+            let tags = split_tags(&link.tags).join(",");
+            html.push_str(&format!(
+                "{}<DT><A HREF=\"{}\" ADD_DATE=\"{}\" LAST_MODIFIED=\"{}\"{}>{}</A>\n",
+                indent,
+                escape(link.url.trim()),
+                Utc.from_utc_datetime(&link.time).timestamp(),
+                Utc.from_utc_datetime(&link.updated).timestamp(),
+                if tags.is_empty() { String::new() } else { format!(" TAGS=\"{}\"", escape(&tags)) },
+                escape(&link.text),
+            ));
+            if !link.title.trim().is_empty() && link.title != link.text {
+                html.push_str(&format!("{}<DD>{}\n", indent, escape(&link.title)));
+            }
+        }
+        if !folder.is_empty() {
+            html.push_str("    </DL><p>\n");
+        }
+    }
+    html.push_str("</DL><p>\n");
+    html
+}
+
+// Text between tags on one line with single spaces
+fn collapse(text: &str) -> String {
+    decode_entities(text).split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,303 @@
+// Changing many posts or links at once.  The content is picked first so the change can be previewed
+// and confirmed, then the change is made in one transaction.  Content saved again since it was
+// picked stops the whole change, nothing is half done.
+
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::sql_types::Bool;
+use std::fmt;
+use crate::{has_tag, split_tags, shared_connection};
+use crate::models::{Post, Link, PostRevision, LinkRevision};
+use crate::schema::{posts, links, post_revisions, link_revisions};
+
+/// Which content a bulk change works on, with both ids and a tag only content matching both.
+#[derive(Default, Debug)]
+pub struct Selection {
+    /// The first and last id of each range, a single id is a range of one
+    pub ids: Option<Vec<(i32, i32)>>,
+    pub tag: Option<String>,
+}
+
+/// A post or link picked for a bulk change, as it was when picked.
+#[derive(Clone, Debug)]
+pub struct BulkItem {
+    pub content_type: &'static str,
+    pub id: i32,
+    pub version: i32,
+    /// The post title or the link text
+    pub title: String,
+    pub tags: String,
+}
+
+/// The new tags of one picked post or link.
+#[derive(Debug)]
+pub struct Retag {
+    pub item: BulkItem,
+    pub new_tags: String,
+}
+
+/// Why a bulk change stopped, nothing was changed.
+#[derive(Debug)]
+pub enum BulkError {
+    Changed(String),
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for BulkError {
+    fn from(error: diesel::result::Error) -> BulkError {
+        BulkError::Database(error)
+    }
+}
+
+impl fmt::Display for BulkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BulkError::Changed(message) => write!(f, "Nothing was changed, {}", message),
+            BulkError::Database(error) => write!(f, "Nothing was changed: {}", error),
+        }
+    }
+}
+
+/// Parse a list of id numbers and ranges into sorted ranges, ranges that overlap or touch are
+/// joined.  The ranges are never expanded, the database picks the ids in them.
+///
+/// ```
+/// use nautilus::bulk::parse_id_ranges;
+///
+/// assert_eq!(parse_id_ranges("4-7, 2,9, 8").unwrap(), vec![(2, 2), (4, 9)]);
+/// assert_eq!(parse_id_ranges("1-2000000000").unwrap(), vec![(1, 2000000000)]);
+/// assert!(parse_id_ranges("7-4").is_err());
+/// assert!(parse_id_ranges("four").is_err());
+/// ```
+pub fn parse_id_ranges(ids: &str) -> Result<Vec<(i32, i32)>, String> {
+    let parse_id = |id: &str| id.trim().parse::<i32>()
+        .map_err(|_| format!("{} is not an id number", id.trim()));
+    let mut parsed = Vec::new();
+    for part in ids.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        match part.find('-') {
+            Some(dash) => {
+                let (first, last) = (parse_id(&part[..dash])?, parse_id(&part[dash + 1..])?);
+                if first > last {
+                    return Err(format!("The range {} runs backwards", part));
+                }
+                parsed.push((first, last));
+            }
+            None => {
+                let id = parse_id(part)?;
+                parsed.push((id, id));
+            }
+        }
+    }
+    if parsed.is_empty() {
+        return Err(String::from("No id numbers given"));
+    }
+    parsed.sort_unstable();
+    let mut joined: Vec<(i32, i32)> = Vec::new();
+    for (first, last) in parsed {
+        match joined.last_mut() {
+            Some(previous) if first <= previous.1.saturating_add(1) => previous.1 = previous.1.max(last),
+            _ => joined.push((first, last)),
+        }
+    }
+    Ok(joined)
+}
+
+/// Add and remove tags in a comma separated tags field.  Tags are compared ignoring case, added
+/// tags go at the end and a tag in both lists is removed.
+///
+/// ```
+/// use nautilus::bulk::retag;
+///
+/// assert_eq!(retag("old, rust", &["new"], &["Old"]), "rust, new");
+/// assert_eq!(retag("rust", &["Rust"], &[]), "rust");
+/// ```
+pub fn retag(tags: &str, add: &[&str], remove: &[&str]) -> String {
+    let mut retagged: Vec<&str> = Vec::new();
+    for tag in split_tags(tags).into_iter().chain(add.iter().map(|tag| tag.trim())) {
+        let removed = remove.iter().any(|removed| removed.trim().eq_ignore_ascii_case(tag));
+        let duplicate = retagged.iter().any(|kept| kept.eq_ignore_ascii_case(tag));
+        if !tag.is_empty() && !removed && !duplicate {
+            retagged.push(tag);
+        }
+    }
+    retagged.join(", ")
+}
+
+/// Pick the posts or links (content type "post" or "link") a bulk change works on, leaving out
+/// the trash.
+///
+/// ```
+/// use nautilus::bulk::{select_content, Selection};
+///
+/// fn pick_some_links() {
+///   let selection = Selection { ids: Some(vec![(4, 6)]), tag: Some(String::from("rust")) };
+///   for item in select_content("link", &selection).unwrap() {
+///     println!("{} {}", item.id, item.title);
+///   }
+/// }
+/// ```
+pub fn select_content(content_type: &str, selection: &Selection) -> QueryResult<Vec<BulkItem>> {
+    let connection = shared_connection();
+
+    let items: Vec<BulkItem> = match content_type {
+        "post" => {
+            let mut query = posts::table.filter(posts::trashed.is_null()).order(posts::id.asc()).into_boxed();
+            if let Some(ranges) = &selection.ids {
+                let mut in_ranges: Box<dyn BoxableExpression<posts::table, Pg, SqlType = Bool>> = Box::new(false.into_sql::<Bool>());
+                for &(first, last) in ranges {
+                    in_ranges = Box::new(in_ranges.or(posts::id.between(first, last)));
+                }
+                query = query.filter(in_ranges);
+            }
+            query.load::<Post>(&*connection)?
+                .into_iter()
+                .map(|post| BulkItem { content_type: "post", id: post.id, version: post.version, title: post.title, tags: post.tags })
+                .collect()
+        }
+        "link" => {
+            let mut query = links::table.filter(links::trashed.is_null()).order(links::id.asc()).into_boxed();
+            if let Some(ranges) = &selection.ids {
+                let mut in_ranges: Box<dyn BoxableExpression<links::table, Pg, SqlType = Bool>> = Box::new(false.into_sql::<Bool>());
+                for &(first, last) in ranges {
+                    in_ranges = Box::new(in_ranges.or(links::id.between(first, last)));
+                }
+                query = query.filter(in_ranges);
+            }
+            query.load::<Link>(&*connection)?
+                .into_iter()
+                .map(|link| BulkItem { content_type: "link", id: link.id, version: link.version, title: link.text, tags: link.tags })
+                .collect()
+        }
+        _ => panic!("Unknown content type {}", content_type),
+    };
+    Ok(match &selection.tag {
+        Some(tag) => items.into_iter().filter(|item| has_tag(&item.tags, tag)).collect(),
+        None => items,
+    })
+}
+
+/// The retags of the picked content, content whose tags don't change is left out.
+pub fn plan_retag(items: Vec<BulkItem>, add: &[&str], remove: &[&str]) -> Vec<Retag> {
+    items.into_iter()
+        .map(|item| {
+            let new_tags = retag(&item.tags, add, remove);
+            Retag { item, new_tags }
+        })
+        .filter(|planned| planned.new_tags != planned.item.tags)
+        .collect()
+}
+
+/// Save new tags in one transaction, each change is a new version with the old one kept as a
+/// revision.  Returns the number of posts and links changed.
+///
+/// ```
+/// use nautilus::bulk::{apply_retag, plan_retag, select_content, Selection};
+///
+/// fn retag_the_old_posts() {
+///   let selection = Selection { ids: None, tag: Some(String::from("old")) };
+///   let planned = plan_retag(select_content("post", &selection).unwrap(), &["new"], &["old"]);
+///   apply_retag(&planned).unwrap();
+/// }
+/// ```
+pub fn apply_retag(retags: &[Retag]) -> Result<usize, BulkError> {
+    let connection = shared_connection();
+    let now = chrono::Utc::now().naive_utc();
+
+    connection.transaction::<_, BulkError, _>(|| {
+        for planned in retags {
+            let item = &planned.item;
+            match item.content_type {
+                "post" => {
+                    let post = posts::table.find(item.id)
+                        .filter(posts::trashed.is_null())
+                        .get_result::<Post>(&*connection)
+                        .optional()?;
+                    let post = unchanged_since_picked(item, post.map(|post| (post.version, post)))?;
+                    diesel::insert_into(post_revisions::table)
+                        .values(&PostRevision::from(&post))
+                        .execute(&*connection)?;
+                    diesel::update(&post)
+                        .set((posts::tags.eq(&planned.new_tags), posts::version.eq(post.version + 1), posts::updated.eq(now)))
+                        .execute(&*connection)?;
+                }
+                "link" => {
+                    let link = links::table.find(item.id)
+                        .filter(links::trashed.is_null())
+                        .get_result::<Link>(&*connection)
+                        .optional()?;
+                    let link = unchanged_since_picked(item, link.map(|link| (link.version, link)))?;
+                    diesel::insert_into(link_revisions::table)
+                        .values(&LinkRevision::from(&link))
+                        .execute(&*connection)?;
+                    diesel::update(&link)
+                        .set((links::tags.eq(&planned.new_tags), links::version.eq(link.version + 1), links::updated.eq(now)))
+                        .execute(&*connection)?;
+                }
+                _ => panic!("Unknown content type {}", item.content_type),
+            }
+        }
+        Ok(retags.len())
+    })
+}
+
+/// Move the picked content to the trash in one transaction.  Returns the number of posts and links
+/// trashed.  Content saved again since it was picked stops the whole change.
+///
+/// ```
+/// use nautilus::*;
+/// use nautilus::bulk::{apply_delete, select_content, BulkError, Selection};
+/// use nautilus::models::{NewLink, Link};
+///
+/// // Only run against a database, with N4_DATABASE_URL set
+/// if std::env::var("N4_DATABASE_URL").is_ok() {
+///   let link = create_link(&NewLink { text: "Bulk test", title: "", url: "https://bulk.example/", tags: "" });
+///   let selection = Selection { ids: Some(vec![(link.id, link.id)]), tag: None };
+///   let picked = select_content("link", &selection).unwrap();
+///
+///   // Edited between the preview and the confirmation
+///   let edited = Link { text: String::from("Bulk test, edited"), ..read_link(link.id) };
+///   assert_eq!(update_link(&edited).unwrap(), 1);
+///
+///   assert!(matches!(apply_delete(&picked), Err(BulkError::Changed(_))));
+///   assert!(read_link(link.id).trashed.is_none());
+///   delete_link(link.id);
+/// }
+/// ```
+pub fn apply_delete(items: &[BulkItem]) -> Result<usize, BulkError> {
+    let connection = shared_connection();
+    let now = chrono::Utc::now().naive_utc();
+
+    connection.transaction::<_, BulkError, _>(|| {
+        for item in items {
+            let trashed = match item.content_type {
+                "post" => diesel::update(
+                    posts::table.find(item.id).filter(posts::version.eq(item.version)).filter(posts::trashed.is_null())
+                )
+                    .set(posts::trashed.eq(now))
+                    .execute(&*connection)?,
+                "link" => diesel::update(
+                    links::table.find(item.id).filter(links::version.eq(item.version)).filter(links::trashed.is_null())
+                )
+                    .set(links::trashed.eq(now))
+                    .execute(&*connection)?,
+                _ => panic!("Unknown content type {}", item.content_type),
+            };
+            if trashed == 0 {
+                return Err(changed_error(item));
+            }
+        }
+        Ok(items.len())
+    })
+}
+
+// The content as it is now, if it's still the version that was picked
+fn unchanged_since_picked<T>(item: &BulkItem, current: Option<(i32, T)>) -> Result<T, BulkError> {
+    match current {
+        Some((version, content)) if version == item.version => Ok(content),
+        _ => Err(changed_error(item)),
+    }
+}
+
+fn changed_error(item: &BulkItem) -> BulkError {
+    BulkError::Changed(format!("{} {} was changed or deleted since it was picked", item.content_type, item.id))
+}
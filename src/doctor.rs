@@ -0,0 +1,190 @@
+// Integrity checks for the content tables, the things the foreign keys can't catch.  Every check is
+// a query listing its problems as (subject, detail) text rows, and the checks with a safe repair
+// have a statement fixing those rows.
+
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use diesel::sql_types::Text;
+
+/// One problem a check found, like subject "post 3" with what's wrong in the detail.
+#[derive(QueryableByName, Clone, Debug)]
+pub struct Problem {
+    #[sql_type = "Text"]
+    pub subject: String,
+    #[sql_type = "Text"]
+    pub detail: String,
+}
+
+/// What a check found, and what is still wrong after fixing (all of it when not fixing).
+pub struct CheckResult {
+    pub name: &'static str,
+    pub found: Vec<Problem>,
+    pub remaining: Vec<Problem>,
+    pub fixable: bool,
+}
+
+struct Check {
+    name: &'static str,
+    find: &'static str,
+    fix: Option<&'static str>,
+}
+
+// In order, fixing the nodes with missing content leaves them for the orphaned node check.
+const CHECKS: &[Check] = &[
+    Check {
+        name: "nodes with missing content",
+        find: "SELECT 'node ' || n.id AS subject,
+                      'its child ' || n.child_content_type || ' ' || n.child || ' does not exist' AS detail
+               FROM nodes n
+               WHERE (n.child_content_type = 'post' AND NOT EXISTS (SELECT 1 FROM posts p WHERE p.id = n.child))
+               OR (n.child_content_type = 'link' AND NOT EXISTS (SELECT 1 FROM links l WHERE l.id = n.child))
+               ORDER BY n.id",
+        fix: Some("UPDATE nodes n SET child = NULL, child_content_type = 'none'
+                   WHERE (n.child_content_type = 'post' AND NOT EXISTS (SELECT 1 FROM posts p WHERE p.id = n.child))
+                   OR (n.child_content_type = 'link' AND NOT EXISTS (SELECT 1 FROM links l WHERE l.id = n.child))"),
+    },
+    Check {
+        name: "orphaned nodes",
+        find: "SELECT 'node ' || n.id AS subject, 'no content belongs to it' AS detail
+               FROM nodes n
+               WHERE n.child IS NULL
+               AND NOT EXISTS (SELECT 1 FROM posts p WHERE p.parent = n.id)
+               AND NOT EXISTS (SELECT 1 FROM links l WHERE l.parent = n.id)
+               ORDER BY n.id",
+        fix: Some("DELETE FROM nodes n
+                   WHERE n.child IS NULL
+                   AND NOT EXISTS (SELECT 1 FROM posts p WHERE p.parent = n.id)
+                   AND NOT EXISTS (SELECT 1 FROM links l WHERE l.parent = n.id)"),
+    },
+    Check {
+        name: "content with a broken parent",
+        find: "SELECT c.content_type || ' ' || c.id AS subject,
+                      CASE
+                          WHEN n.id IS NULL THEN 'its parent node ' || c.parent || ' does not exist'
+                          WHEN n.child IS NULL THEN 'its parent node ' || n.id || ' has no child'
+                          ELSE 'its parent node ' || n.id || ' belongs to ' || n.child_content_type || ' ' || n.child
+                      END AS detail
+               FROM (SELECT 'post' AS content_type, id, parent FROM posts
+                     UNION ALL SELECT 'link', id, parent FROM links) c
+               LEFT JOIN nodes n ON n.id = c.parent
+               WHERE c.parent IS NOT NULL
+               AND (n.id IS NULL OR n.child IS DISTINCT FROM c.id OR n.child_content_type <> c.content_type)
+               ORDER BY c.content_type, c.id",
+        // Only the safe cases, a missing node is dropped and an empty node gets its child back
+        fix: Some("UPDATE posts p SET parent = NULL WHERE parent IS NOT NULL AND NOT EXISTS (SELECT 1 FROM nodes n WHERE n.id = p.parent);
+                   UPDATE links l SET parent = NULL WHERE parent IS NOT NULL AND NOT EXISTS (SELECT 1 FROM nodes n WHERE n.id = l.parent);
+                   UPDATE nodes n SET child = p.id, child_content_type = 'post' FROM posts p
+                       WHERE p.parent = n.id AND n.child IS NULL
+                       AND (SELECT count(*) FROM posts other WHERE other.parent = n.id) = 1
+                       AND NOT EXISTS (SELECT 1 FROM links l WHERE l.parent = n.id);
+                   UPDATE nodes n SET child = l.id, child_content_type = 'link' FROM links l
+                       WHERE l.parent = n.id AND n.child IS NULL
+                       AND (SELECT count(*) FROM links other WHERE other.parent = n.id) = 1
+                       AND NOT EXISTS (SELECT 1 FROM posts p WHERE p.parent = n.id)"),
+    },
+    Check {
+        name: "nodes their content doesn't belong to",
+        find: "SELECT 'node ' || n.id AS subject,
+                      'its child ' || c.content_type || ' ' || c.id || ' has '
+                          || COALESCE('node ' || c.parent || ' as parent', 'no parent') AS detail
+               FROM nodes n
+               JOIN (SELECT 'post' AS content_type, id, parent FROM posts
+                     UNION ALL SELECT 'link', id, parent FROM links) c
+                   ON c.content_type = n.child_content_type AND c.id = n.child
+               WHERE c.parent IS DISTINCT FROM n.id
+               ORDER BY n.id",
+        // Content without a parent is given back to the only node claiming it
+        fix: Some("UPDATE posts p SET parent = n.id FROM nodes n
+                       WHERE p.parent IS NULL AND n.child_content_type = 'post' AND n.child = p.id
+                       AND (SELECT count(*) FROM nodes other WHERE other.child_content_type = 'post' AND other.child = p.id) = 1;
+                   UPDATE links l SET parent = n.id FROM nodes n
+                       WHERE l.parent IS NULL AND n.child_content_type = 'link' AND n.child = l.id
+                       AND (SELECT count(*) FROM nodes other WHERE other.child_content_type = 'link' AND other.child = l.id) = 1"),
+    },
+    Check {
+        name: "revision gaps",
+        find: "SELECT s.content_type || ' ' || s.id AS subject,
+                      'missing revisions ' || string_agg(s.version::TEXT, ', ' ORDER BY s.version) AS detail
+               FROM (SELECT 'post' AS content_type, id::TEXT AS id, generate_series(1, max(version)) AS version
+                         FROM post_revisions GROUP BY id
+                     UNION ALL SELECT 'link', id::TEXT, generate_series(1, max(version)) FROM link_revisions GROUP BY id
+                     UNION ALL SELECT 'node', id::TEXT, generate_series(1, max(version)) FROM node_revisions GROUP BY id
+                     UNION ALL SELECT 'system', key, generate_series(1, max(version)) FROM system_revisions GROUP BY key) s
+               WHERE NOT EXISTS (
+                   SELECT 1 FROM (SELECT 'post' AS content_type, id::TEXT AS id, version FROM post_revisions
+                                  UNION ALL SELECT 'link', id::TEXT, version FROM link_revisions
+                                  UNION ALL SELECT 'node', id::TEXT, version FROM node_revisions
+                                  UNION ALL SELECT 'system', key, version FROM system_revisions) r
+                   WHERE r.content_type = s.content_type AND r.id = s.id AND r.version = s.version)
+               GROUP BY s.content_type, s.id
+               ORDER BY s.content_type, s.id",
+        fix: None,
+    },
+    Check {
+        name: "version mismatches",
+        find: "SELECT c.content_type || ' ' || c.id AS subject,
+                      'version ' || c.version || ' but revision ' || max(r.version) || ' exists' AS detail
+               FROM (SELECT 'post' AS content_type, id::TEXT AS id, version FROM posts
+                     UNION ALL SELECT 'link', id::TEXT, version FROM links
+                     UNION ALL SELECT 'node', id::TEXT, version FROM nodes
+                     UNION ALL SELECT 'system', key, version FROM system) c
+               JOIN (SELECT 'post' AS content_type, id::TEXT AS id, version FROM post_revisions
+                     UNION ALL SELECT 'link', id::TEXT, version FROM link_revisions
+                     UNION ALL SELECT 'node', id::TEXT, version FROM node_revisions
+                     UNION ALL SELECT 'system', key, version FROM system_revisions) r
+                   ON r.content_type = c.content_type AND r.id = c.id
+               GROUP BY c.content_type, c.id, c.version
+               HAVING max(r.version) >= c.version
+               ORDER BY c.content_type, c.id",
+        // The current version moves up past its history
+        fix: Some("UPDATE posts c SET version = r.version + 1
+                       FROM (SELECT id, max(version) AS version FROM post_revisions GROUP BY id) r
+                       WHERE r.id = c.id AND r.version >= c.version;
+                   UPDATE links c SET version = r.version + 1
+                       FROM (SELECT id, max(version) AS version FROM link_revisions GROUP BY id) r
+                       WHERE r.id = c.id AND r.version >= c.version;
+                   UPDATE nodes c SET version = r.version + 1
+                       FROM (SELECT id, max(version) AS version FROM node_revisions GROUP BY id) r
+                       WHERE r.id = c.id AND r.version >= c.version;
+                   UPDATE system c SET version = r.version + 1
+                       FROM (SELECT key, max(version) AS version FROM system_revisions GROUP BY key) r
+                       WHERE r.key = c.key AND r.version >= c.version"),
+    },
+];
+
+/// Run every content check, and with fix the safe repairs (all in one transaction).
+///
+/// ```
+/// use nautilus::*;
+/// use nautilus::doctor::run_checks;
+///
+/// fn check_the_content() {
+///   let connection = establish_connection();
+///   for check in run_checks(&connection, false).unwrap() {
+///     println!("{}: {} problems", check.name, check.found.len());
+///   }
+/// }
+/// ```
+pub fn run_checks(connection: &PgConnection, fix: bool) -> QueryResult<Vec<CheckResult>> {
+    connection.transaction(|| {
+        let mut results = Vec::new();
+        for check in CHECKS {
+            let found = diesel::sql_query(check.find).load::<Problem>(connection)?;
+            let remaining = match check.fix {
+                Some(fix_sql) if fix && !found.is_empty() => {
+                    connection.batch_execute(fix_sql)?;
+                    diesel::sql_query(check.find).load::<Problem>(connection)?
+                }
+                _ => found.clone(),
+            };
+            results.push(CheckResult {
+                name: check.name,
+                found,
+                remaining,
+                fixable: check.fix.is_some(),
+            });
+        }
+        Ok(results)
+    })
+}
@@ -0,0 +1,180 @@
+// Drafts of editor sessions.  Whatever comes back from the editor is saved to ~/.config/N4/drafts/
+// before it goes to the database and removed once it's saved, so a failed database write doesn't
+// lose a long edit.  Drafts left behind can be listed and resubmitted with the recover command.
+// Each site profile has its own drafts, a draft is only ever resubmitted to the database it came
+// from.
+
+use chrono::NaiveDateTime;
+use serde_derive::{Serialize, Deserialize};
+use std::fs;
+use std::path::PathBuf;
+use crate::config::active_profile;
+use crate::import::{import_posts, import_system, ImportError, ImportSummary, OnConflict, PostRecord, SystemRecord};
+
+/// The content of a draft, a new record (no id) or an edit of an existing one.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "content_type", content = "record", rename_all = "lowercase")]
+pub enum DraftRecord {
+    Post(PostRecord),
+    System(SystemRecord),
+}
+
+/// A draft saved from the editor workflow.
+#[derive(Serialize, Deserialize)]
+pub struct Draft {
+    pub saved: NaiveDateTime,
+    #[serde(flatten)]
+    pub record: DraftRecord,
+}
+
+impl Draft {
+    pub fn new(record: DraftRecord) -> Draft {
+        Draft {
+            saved: chrono::Utc::now().naive_utc(),
+            record,
+        }
+    }
+
+    /// The draft file name, the same every time this draft is saved.
+    ///
+    /// ```
+    /// use nautilus::drafts::{Draft, DraftRecord};
+    /// use nautilus::import::PostRecord;
+    ///
+    /// let record: PostRecord = serde_json::from_str(r#"{"id": 3, "title": "Hi", "body": "There"}"#).unwrap();
+    /// let mut draft = Draft::new(DraftRecord::Post(record));
+    /// draft.saved = chrono::NaiveDate::from_ymd(2020, 4, 24).and_hms(21, 9, 3);
+    /// assert_eq!(draft.name(), "20200424-210903-post-3");
+    /// ```
+    pub fn name(&self) -> String {
+        let (content_type, content_id) = match &self.record {
+            DraftRecord::Post(record) => ("post", record.id.map(|id| id.to_string())),
+            DraftRecord::System(record) => ("system", Some(record.key.clone())),
+        };
+        format!(
+            "{}-{}-{}",
+            self.saved.format("%Y%m%d-%H%M%S"),
+            content_type,
+            content_id.unwrap_or_else(|| String::from("new")),
+        )
+    }
+
+    /// A short description for listing, the post title or the system key.
+    pub fn title(&self) -> &str {
+        match &self.record {
+            DraftRecord::Post(record) => &record.title,
+            DraftRecord::System(record) => &record.key,
+        }
+    }
+}
+
+/// The drafts directory, ~/.config/N4/drafts/ or ~/.config/N4/drafts/<profile>/ when a site
+/// profile is in use, created if it doesn't exist yet.
+pub fn drafts_dir() -> Result<PathBuf, String> {
+    let mut drafts_dir = dirs::config_dir()
+        .ok_or_else(|| String::from("No config directory to save drafts in"))?
+        .join("N4")
+        .join("drafts");
+    if let Some((name, _)) = active_profile()? {
+        drafts_dir = drafts_dir.join(name);
+    }
+    fs::create_dir_all(&drafts_dir)
+        .map_err(|e| format!("Could not create the drafts directory {}: {}", drafts_dir.display(), e))?;
+    Ok(drafts_dir)
+}
+
+/// The file a draft is saved to.
+pub fn draft_file(draft: &Draft) -> Result<PathBuf, String> {
+    Ok(drafts_dir()?.join(format!("{}.json", draft.name())))
+}
+
+/// Save a draft (again), returning the file it was saved to.
+///
+/// ```
+/// use nautilus::drafts::{save_draft, remove_draft, Draft, DraftRecord};
+/// use nautilus::import::SystemRecord;
+///
+/// fn save_a_draft() {
+///   let record: SystemRecord = serde_json::from_str(r#"{"key": "routes", "data": "/post/"}"#).unwrap();
+///   let draft = Draft::new(DraftRecord::System(record));
+///   let draft_file = save_draft(&draft).unwrap();
+///   // Saved to the database without trouble
+///   remove_draft(&draft).unwrap();
+/// }
+/// ```
+pub fn save_draft(draft: &Draft) -> Result<PathBuf, String> {
+    let draft_file = draft_file(draft)?;
+    let contents = serde_json::to_string_pretty(draft).expect("Could not serialize the draft");
+    fs::write(&draft_file, contents)
+        .map_err(|e| format!("Could not save the draft {}: {}", draft_file.display(), e))?;
+    Ok(draft_file)
+}
+
+/// Remove a saved draft, a draft that was never saved is fine.
+pub fn remove_draft(draft: &Draft) -> Result<(), String> {
+    let draft_file = draft_file(draft)?;
+    match fs::remove_file(&draft_file) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("Could not remove the draft {}: {}", draft_file.display(), e)),
+    }
+}
+
+/// Every saved draft, oldest first.  Files that can't be read as drafts are left out.
+pub fn list_drafts() -> Result<Vec<Draft>, String> {
+    let drafts_dir = drafts_dir()?;
+    let entries = fs::read_dir(&drafts_dir)
+        .map_err(|e| format!("Could not read the drafts directory {}: {}", drafts_dir.display(), e))?;
+    let mut drafts: Vec<Draft> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map(|extension| extension == "json").unwrap_or(false))
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .filter_map(|contents| serde_json::from_str(&contents).ok())
+        .collect();
+    drafts.sort_by_key(|draft| draft.name());
+    Ok(drafts)
+}
+
+/// Find a saved draft by name.
+pub fn find_draft(name: &str) -> Result<Option<Draft>, String> {
+    let name = name.trim_end_matches(".json");
+    Ok(list_drafts()?.into_iter().find(|draft| draft.name() == name))
+}
+
+/// Save a draft to the database.
+///
+/// New posts are inserted.  Edits overwrite the record they were made from, unless it has been
+/// saved again since (a newer version), then the draft is left in the summary as skipped.
+///
+/// ```
+/// use nautilus::*;
+/// use nautilus::drafts::{submit_draft, Draft, DraftRecord};
+/// use nautilus::import::PostRecord;
+/// use nautilus::models::{NewPost, Post};
+///
+/// // Only run against a database, with N4_DATABASE_URL set
+/// if std::env::var("N4_DATABASE_URL").is_ok() {
+///   let slug = format!("draft-test-{}", chrono::Utc::now().timestamp_nanos());
+///   let post = create_post(&NewPost { title: "Draft test", body: "First", tags: "", summary: "", slug: &slug });
+///   let record: PostRecord = serde_json::from_value(serde_json::json!({
+///     "id": post.id, "title": "Draft test", "body": "From the draft", "version": post.version,
+///   })).unwrap();
+///   let draft = Draft::new(DraftRecord::Post(record));
+///
+///   // Edited after the draft was taken
+///   let edited = Post { body: String::from("Second"), ..read_post(post.id) };
+///   assert_eq!(update_post(&edited).unwrap(), 1);
+///
+///   let summary = submit_draft(&draft).unwrap();
+///   assert_eq!(summary.skipped.len(), 1);
+///   assert_eq!(read_post(post.id).body, "Second");
+///   delete_post(post.id);
+/// }
+/// ```
+pub fn submit_draft(draft: &Draft) -> Result<ImportSummary, ImportError> {
+    match &draft.record {
+        DraftRecord::Post(record) => import_posts(std::slice::from_ref(record), OnConflict::Overwrite, false),
+        DraftRecord::System(record) => import_system(std::slice::from_ref(record), OnConflict::Overwrite, false),
+    }
+}
@@ -0,0 +1,81 @@
+// Editing text in the user's editor.  The editor comes from $VISUAL, then $EDITOR (either can be
+// set in the .env config file), then the site profile, and falls back to vim.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use subprocess::{Exec, ExitStatus};
+
+/// The editor command to run, from $VISUAL, $EDITOR or the site profile with vim as the fallback.
+///
+/// The command can include arguments, like "code --wait".
+pub fn editor_command() -> String {
+    crate::load_env();
+    let profile_editor = crate::config::active_profile().ok()
+        .and_then(|profile| profile)
+        .and_then(|(_, profile)| profile.editor);
+    ["VISUAL", "EDITOR"].iter()
+        .filter_map(|variable| env::var(variable).ok())
+        .chain(profile_editor)
+        .find(|command| !command.trim().is_empty())
+        .unwrap_or_else(|| String::from("vim"))
+}
+
+/// The file extension for a body, so the editor can pick the right syntax highlighting.
+///
+/// ```
+/// use nautilus::editor::body_extension;
+///
+/// assert_eq!(body_extension("<p>Hi there</p>"), "html");
+/// assert_eq!(body_extension("# Hi there"), "md");
+/// ```
+pub fn body_extension(body: &str) -> &'static str {
+    if body.trim_start().starts_with('<') {
+        "html"
+    } else {
+        "md"
+    }
+}
+
+/// Open text in the editor and return it once the editor is closed.
+///
+/// The text is written to a temporary file with the given extension (like "md"), which is removed
+/// afterwards.  An editor that can't be started or exits with an error status is an Err, and the
+/// edit should be thrown away.
+///
+/// ```
+/// use nautilus::editor::edit_text;
+///
+/// fn edit_text_test() {
+///   let body = edit_text("# Hi there", "md").unwrap();
+/// }
+/// ```
+pub fn edit_text(text: &str, extension: &str) -> Result<String, String> {
+    let mut file = tempfile::Builder::new()
+        .prefix("n4-")
+        .suffix(&format!(".{}", extension))
+        .tempfile()
+        .map_err(|e| format!("Could not create a temporary file to edit: {}", e))?;
+    file.write_all(text.as_bytes())
+        .and_then(|_| file.flush())
+        .map_err(|e| format!("Could not write the temporary file to edit: {}", e))?;
+
+    let command = editor_command();
+    let mut words = command.split_whitespace();
+    let program = words.next().unwrap_or("vim");
+    let arguments: Vec<&str> = words.collect();
+    let status = Exec::cmd(program)
+        .args(&arguments)
+        .arg(file.path())
+        .join()
+        .map_err(|e| format!("Could not start the editor {}: {}", command, e))?;
+    match status {
+        ExitStatus::Exited(0) => {}
+        ExitStatus::Exited(code) => return Err(format!("The editor {} exited with status {}", command, code)),
+        status => return Err(format!("The editor {} stopped unexpectedly: {:?}", command, status)),
+    }
+
+    // Read back by path, editors often replace the file rather than write to it
+    fs::read_to_string(file.path())
+        .map_err(|e| format!("Could not read the edited file: {}", e))
+}
@@ -0,0 +1,578 @@
+// Importing exported JSON records back into the database.  Each import runs in one transaction so
+// a file is either fully imported or not at all, and a dry run is a transaction rolled back.  A
+// dry run doesn't insert posts or links, Postgres never gives back the ids a rolled back insert
+// took, so the inserts are reported from the file instead.
+
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_derive::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+use crate::shared_connection;
+use crate::models::{Post, Link, System, PostRevision, LinkRevision, SystemRevision};
+use crate::schema::{posts, links, system, post_revisions, link_revisions, system_revisions};
+use crate::systemdata::check_system_data;
+
+/// What to do when an imported record already exists in the database.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OnConflict {
+    /// Leave the database record alone
+    Skip,
+    /// Replace the database record with its next version, unless the imported version is older
+    Overwrite,
+    /// Insert the imported record under a new id (not possible for system entries)
+    NewId,
+    /// Stop and roll back the whole import
+    Fail,
+}
+
+impl FromStr for OnConflict {
+    type Err = String;
+
+    fn from_str(on_conflict: &str) -> Result<OnConflict, String> {
+        match on_conflict {
+            "skip" => Ok(OnConflict::Skip),
+            "overwrite" => Ok(OnConflict::Overwrite),
+            "new-id" => Ok(OnConflict::NewId),
+            "fail" => Ok(OnConflict::Fail),
+            _ => Err(format!("Unknown conflict strategy {}, use skip, overwrite, new-id or fail", on_conflict)),
+        }
+    }
+}
+
+/// Why an import stopped, nothing from the file was saved.
+#[derive(Debug)]
+pub enum ImportError {
+    Conflict(String),
+    Invalid(String),
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for ImportError {
+    fn from(error: diesel::result::Error) -> ImportError {
+        ImportError::Database(error)
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Conflict(message) => write!(f, "Import stopped, nothing was saved: {}", message),
+            ImportError::Invalid(message) => write!(f, "Import stopped, nothing was saved: {}", message),
+            ImportError::Database(error) => write!(f, "Import stopped, nothing was saved: {}", error),
+        }
+    }
+}
+
+/// The records an import inserted, updated and skipped (with the reason), by id or system key.
+///
+/// Every inserted and updated record also lists its fields, the ones that changed for an update.
+#[derive(Default, Debug)]
+pub struct ImportSummary {
+    pub inserted: Vec<String>,
+    pub updated: Vec<String>,
+    pub skipped: Vec<(String, String)>,
+    pub changes: Vec<(String, Vec<FieldChange>)>,
+}
+
+/// A field an import changes, with the database value (null for an insert) and the imported value
+/// as JSON.
+#[derive(Debug)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// A post in an import file.  Only the title and body are needed, an id that isn't in the database
+/// is kept and missing fields get the database defaults (or keep the current value on overwrite).
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct PostRecord {
+    pub id: Option<i32>,
+    pub title: String,
+    pub body: String,
+    pub time: Option<chrono::NaiveDateTime>,
+    pub tags: Option<String>,
+    pub summary: Option<String>,
+    pub version: Option<i32>,
+    pub updated: Option<chrono::NaiveDateTime>,
+    pub slug: Option<String>,
+}
+
+/// A link in an import file, only the text, title and url are needed.
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct LinkRecord {
+    pub id: Option<i32>,
+    pub text: String,
+    pub title: String,
+    pub url: String,
+    pub tags: Option<String>,
+    pub time: Option<chrono::NaiveDateTime>,
+    pub version: Option<i32>,
+    pub updated: Option<chrono::NaiveDateTime>,
+}
+
+/// A system entry in an import file, only the key and data (any JSON value) are needed.
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct SystemRecord {
+    pub key: String,
+    pub data: serde_json::Value,
+    pub time: Option<chrono::NaiveDateTime>,
+    pub version: Option<i32>,
+    pub updated: Option<chrono::NaiveDateTime>,
+}
+
+/// Insert new posts and resolve posts that already exist with the conflict strategy.  A post in
+/// the trash is only skipped or given a new id, anything else needs it restored or purged first.
+///
+/// ```
+/// use nautilus::import::{import_posts, OnConflict, PostRecord};
+///
+/// fn import_a_post() {
+///   let records: Vec<PostRecord> = serde_json::from_str(r#"[{"title": "Hi", "body": "There"}]"#).unwrap();
+///   let summary = import_posts(&records, OnConflict::Skip, false).unwrap();
+///   println!("Inserted {:?}", summary.inserted);
+/// }
+/// ```
+pub fn import_posts(records: &[PostRecord], on_conflict: OnConflict, dry_run: bool) -> Result<ImportSummary, ImportError> {
+    run_import(dry_run, |connection| import_post_records(connection, records, on_conflict, dry_run))
+}
+
+fn import_post_records(connection: &PgConnection, records: &[PostRecord], on_conflict: OnConflict, dry_run: bool) -> Result<ImportSummary, ImportError> {
+    let mut summary = ImportSummary::default();
+    let mut planned_ids = Vec::new();
+    for record in records {
+        let existing = match record.id {
+            Some(record_id) => posts::table.find(record_id).get_result::<Post>(connection).optional()?,
+            None => None,
+        };
+        let existing = match existing {
+            Some(existing) => existing,
+            None if dry_run => {
+                plan_insert(&mut summary, &mut planned_ids, "post", record.id, record, on_conflict)?;
+                continue;
+            }
+            None => {
+                let post = insert_post_record(connection, record, record.id)?;
+                if record.id.is_some() {
+                    move_id_sequence_past(connection, "posts", post.id)?;
+                }
+                summary.changes.push((post.id.to_string(), insert_changes(&post)));
+                summary.inserted.push(post.id.to_string());
+                continue;
+            }
+        };
+
+        if existing.trashed.is_some() && on_conflict != OnConflict::NewId {
+            if on_conflict == OnConflict::Skip {
+                summary.skipped.push((existing.id.to_string(), String::from("in the trash, restore or purge it first")));
+                continue;
+            }
+            return Err(ImportError::Conflict(format!("post {} is in the trash, restore or purge it first", existing.id)));
+        }
+
+        match on_conflict {
+            OnConflict::Skip => summary.skipped.push((existing.id.to_string(), String::from("already exists"))),
+            OnConflict::Fail => return Err(ImportError::Conflict(format!("post {} already exists", existing.id))),
+            OnConflict::NewId if dry_run => {
+                let inserted = format!("new (from {})", existing.id);
+                summary.changes.push((inserted.clone(), insert_changes(record)));
+                summary.inserted.push(inserted);
+            }
+            OnConflict::NewId => {
+                let post = insert_post_record(connection, record, None)?;
+                let inserted = format!("{} (from {})", post.id, existing.id);
+                summary.changes.push((inserted.clone(), insert_changes(&post)));
+                summary.inserted.push(inserted);
+            }
+            OnConflict::Overwrite => {
+                if let Some(reason) = stale_reason(record.version, existing.version) {
+                    summary.skipped.push((existing.id.to_string(), reason));
+                    continue;
+                }
+                let before = serde_json::to_value(&existing).expect("Could not compare records");
+                diesel::insert_into(post_revisions::table)
+                    .values(&PostRevision::from(&existing))
+                    .execute(connection)?;
+                let post = Post {
+                    title: record.title.clone(),
+                    body: record.body.clone(),
+                    time: record.time.unwrap_or(existing.time),
+                    tags: record.tags.clone().unwrap_or_else(|| existing.tags.clone()),
+                    summary: record.summary.clone().unwrap_or_else(|| existing.summary.clone()),
+                    version: next_version(existing.version),
+                    updated: chrono::Utc::now().naive_utc(),
+                    slug: record.slug.clone().unwrap_or_else(|| existing.slug.clone()),
+                    ..existing
+                };
+                summary.changes.push((post.id.to_string(), field_changes(&before, &post)));
+                diesel::update(&post).set(&post).execute(connection)?;
+                summary.updated.push(post.id.to_string());
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// Insert new links and resolve links that already exist with the conflict strategy.  A link in
+/// the trash is only skipped or given a new id, anything else needs it restored or purged first.
+///
+/// ```
+/// use nautilus::import::{import_links, OnConflict, LinkRecord};
+///
+/// fn import_a_link() {
+///   let records: Vec<LinkRecord> = serde_json::from_str(
+///     r#"[{"text": "Rust", "title": "Rust", "url": "https://www.rust-lang.org/"}]"#
+///   ).unwrap();
+///   let summary = import_links(&records, OnConflict::Skip, false).unwrap();
+///   println!("Inserted {:?}", summary.inserted);
+/// }
+/// ```
+pub fn import_links(records: &[LinkRecord], on_conflict: OnConflict, dry_run: bool) -> Result<ImportSummary, ImportError> {
+    run_import(dry_run, |connection| import_link_records(connection, records, on_conflict, dry_run))
+}
+
+fn import_link_records(connection: &PgConnection, records: &[LinkRecord], on_conflict: OnConflict, dry_run: bool) -> Result<ImportSummary, ImportError> {
+    let mut summary = ImportSummary::default();
+    let mut planned_ids = Vec::new();
+    for record in records {
+        let existing = match record.id {
+            Some(record_id) => links::table.find(record_id).get_result::<Link>(connection).optional()?,
+            None => None,
+        };
+        let existing = match existing {
+            Some(existing) => existing,
+            None if dry_run => {
+                plan_insert(&mut summary, &mut planned_ids, "link", record.id, record, on_conflict)?;
+                continue;
+            }
+            None => {
+                let link = insert_link_record(connection, record, record.id)?;
+                if record.id.is_some() {
+                    move_id_sequence_past(connection, "links", link.id)?;
+                }
+                summary.changes.push((link.id.to_string(), insert_changes(&link)));
+                summary.inserted.push(link.id.to_string());
+                continue;
+            }
+        };
+
+        if existing.trashed.is_some() && on_conflict != OnConflict::NewId {
+            if on_conflict == OnConflict::Skip {
+                summary.skipped.push((existing.id.to_string(), String::from("in the trash, restore or purge it first")));
+                continue;
+            }
+            return Err(ImportError::Conflict(format!("link {} is in the trash, restore or purge it first", existing.id)));
+        }
+
+        match on_conflict {
+            OnConflict::Skip => summary.skipped.push((existing.id.to_string(), String::from("already exists"))),
+            OnConflict::Fail => return Err(ImportError::Conflict(format!("link {} already exists", existing.id))),
+            OnConflict::NewId if dry_run => {
+                let inserted = format!("new (from {})", existing.id);
+                summary.changes.push((inserted.clone(), insert_changes(record)));
+                summary.inserted.push(inserted);
+            }
+            OnConflict::NewId => {
+                let link = insert_link_record(connection, record, None)?;
+                let inserted = format!("{} (from {})", link.id, existing.id);
+                summary.changes.push((inserted.clone(), insert_changes(&link)));
+                summary.inserted.push(inserted);
+            }
+            OnConflict::Overwrite => {
+                if let Some(reason) = stale_reason(record.version, existing.version) {
+                    summary.skipped.push((existing.id.to_string(), reason));
+                    continue;
+                }
+                let before = serde_json::to_value(&existing).expect("Could not compare records");
+                diesel::insert_into(link_revisions::table)
+                    .values(&LinkRevision::from(&existing))
+                    .execute(connection)?;
+                let link = Link {
+                    text: record.text.clone(),
+                    title: record.title.clone(),
+                    url: record.url.clone(),
+                    tags: record.tags.clone().unwrap_or_else(|| existing.tags.clone()),
+                    time: record.time.unwrap_or(existing.time),
+                    version: next_version(existing.version),
+                    updated: chrono::Utc::now().naive_utc(),
+                    ..existing
+                };
+                summary.changes.push((link.id.to_string(), field_changes(&before, &link)));
+                diesel::update(&link).set(&link).execute(connection)?;
+                summary.updated.push(link.id.to_string());
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// Import posts and links together in one transaction, like a whole blog.  Either all of them are
+/// saved or none, returns the summary of the posts and the summary of the links.
+///
+/// ```
+/// use nautilus::import::{import_posts_and_links, OnConflict, LinkRecord, PostRecord};
+///
+/// fn import_a_blog() {
+///   let posts: Vec<PostRecord> = serde_json::from_str(r#"[{"title": "Hi", "body": "There"}]"#).unwrap();
+///   let links: Vec<LinkRecord> = serde_json::from_str(r#"[{"text": "Rust", "title": "", "url": "https://www.rust-lang.org/"}]"#).unwrap();
+///   let (posts, links) = import_posts_and_links(&posts, &links, OnConflict::Fail, false).unwrap();
+///   println!("Inserted posts {:?} and links {:?}", posts.inserted, links.inserted);
+/// }
+/// ```
+pub fn import_posts_and_links(
+    posts: &[PostRecord],
+    links: &[LinkRecord],
+    on_conflict: OnConflict,
+    dry_run: bool,
+) -> Result<(ImportSummary, ImportSummary), ImportError> {
+    run_import(dry_run, |connection| Ok((
+        import_post_records(connection, posts, on_conflict, dry_run)?,
+        import_link_records(connection, links, on_conflict, dry_run)?,
+    )))
+}
+
+/// Insert new system entries and resolve keys that already exist with the conflict strategy.
+/// A key in the trash is only skipped, anything else needs it restored or purged first.
+///
+/// System entries are identified by their key so new-id is refused as a conflict.  Every value
+/// has to match the JSON Schema of its key.
+///
+/// ```
+/// use nautilus::import::{import_system, OnConflict, SystemRecord};
+///
+/// fn import_a_system_entry() {
+///   let records: Vec<SystemRecord> = serde_json::from_str(r#"[{"key": "routes", "data": "/post/"}]"#).unwrap();
+///   let summary = import_system(&records, OnConflict::Overwrite, false).unwrap();
+///   println!("Updated {:?}", summary.updated);
+/// }
+/// ```
+pub fn import_system(records: &[SystemRecord], on_conflict: OnConflict, dry_run: bool) -> Result<ImportSummary, ImportError> {
+    run_import(dry_run, |connection| {
+        let mut summary = ImportSummary::default();
+        for record in records {
+            let existing = system::table
+                .filter(system::key.eq(&record.key))
+                .get_result::<System>(connection)
+                .optional()?;
+            let existing = match existing {
+                Some(existing) => existing,
+                None => {
+                    check_system_data(&record.key, &record.data).map_err(ImportError::Invalid)?;
+                    diesel::insert_into(system::table)
+                        .values((
+                            system::key.eq(&record.key),
+                            system::data.eq(&record.data),
+                            record.time.map(|time| system::time.eq(time)),
+                            record.version.map(|version| system::version.eq(version)),
+                            record.updated.map(|updated| system::updated.eq(updated)),
+                        ))
+                        .execute(connection)?;
+                    summary.changes.push((record.key.clone(), insert_changes(record)));
+                    summary.inserted.push(record.key.clone());
+                    continue;
+                }
+            };
+
+            if existing.trashed.is_some() {
+                if on_conflict == OnConflict::Skip {
+                    summary.skipped.push((existing.key.clone(), String::from("in the trash, restore or purge it first")));
+                    continue;
+                }
+                return Err(ImportError::Conflict(format!("system key {} is in the trash, restore or purge it first", existing.key)));
+            }
+
+            match on_conflict {
+                OnConflict::Skip => summary.skipped.push((existing.key, String::from("already exists"))),
+                OnConflict::Fail => return Err(ImportError::Conflict(format!("system key {} already exists", existing.key))),
+                OnConflict::NewId => return Err(ImportError::Conflict(
+                    format!("system key {} already exists and system entries can't get a new id", existing.key)
+                )),
+                OnConflict::Overwrite => {
+                    if let Some(reason) = stale_reason(record.version, existing.version) {
+                        summary.skipped.push((existing.key, reason));
+                        continue;
+                    }
+                    check_system_data(&record.key, &record.data).map_err(ImportError::Invalid)?;
+                    let before = serde_json::to_value(&existing).expect("Could not compare records");
+                    diesel::insert_into(system_revisions::table)
+                        .values(&SystemRevision::from(&existing))
+                        .execute(connection)?;
+                    let entry = System {
+                        data: record.data.clone(),
+                        time: record.time.unwrap_or(existing.time),
+                        version: next_version(existing.version),
+                        updated: chrono::Utc::now().naive_utc(),
+                        ..existing
+                    };
+                    summary.changes.push((entry.key.clone(), field_changes(&before, &entry)));
+                    diesel::update(system::table.filter(system::key.eq(&entry.key)))
+                        .set(&entry)
+                        .execute(connection)?;
+                    summary.updated.push(entry.key);
+                }
+            }
+        }
+        Ok(summary)
+    })
+}
+
+// Imports run in a transaction, a dry run rolls it back at the end so it can report exactly what
+// would have happened.
+fn run_import<T, F>(dry_run: bool, import: F) -> Result<T, ImportError>
+    where F: FnOnce(&PgConnection) -> Result<T, ImportError> {
+    let connection = shared_connection();
+
+    let result = connection.transaction::<_, TransactionEnd<T>, _>(|| {
+        let summary = import(&connection).map_err(TransactionEnd::Failed)?;
+        if dry_run {
+            Err(TransactionEnd::DryRun(summary))
+        } else {
+            Ok(summary)
+        }
+    });
+    match result {
+        Ok(summary) | Err(TransactionEnd::DryRun(summary)) => Ok(summary),
+        Err(TransactionEnd::Failed(e)) => Err(e),
+    }
+}
+
+enum TransactionEnd<T> {
+    Failed(ImportError),
+    DryRun(T),
+}
+
+impl<T> From<diesel::result::Error> for TransactionEnd<T> {
+    fn from(error: diesel::result::Error) -> TransactionEnd<T> {
+        TransactionEnd::Failed(ImportError::Database(error))
+    }
+}
+
+// The fields that differ between the database record and its replacement, the updated time always
+// changes so it's left out.
+fn field_changes<T: Serialize>(before: &serde_json::Value, after: &T) -> Vec<FieldChange> {
+    let after = serde_json::to_value(after).expect("Could not compare records");
+    let mut changes = Vec::new();
+    if let (Some(before), Some(after)) = (before.as_object(), after.as_object()) {
+        for (field, new) in after {
+            let old = before.get(field).cloned().unwrap_or(serde_json::Value::Null);
+            if field != "updated" && &old != new {
+                changes.push(FieldChange {
+                    field: field.clone(),
+                    old: old.to_string(),
+                    new: new.to_string(),
+                });
+            }
+        }
+    }
+    changes
+}
+
+// Every field of an inserted record that has a value, the id is already in the summary
+fn insert_changes<T: Serialize>(record: &T) -> Vec<FieldChange> {
+    let record = serde_json::to_value(record).expect("Could not compare records");
+    record.as_object()
+        .map(|fields| fields.iter()
+            .filter(|(field, value)| *field != "id" && !value.is_null())
+            .map(|(field, value)| FieldChange {
+                field: field.clone(),
+                old: String::from("null"),
+                new: value.to_string(),
+            })
+            .collect())
+        .unwrap_or_default()
+}
+
+// Report the insert a dry run skips.  An id inserted earlier in the file would exist by the time
+// the record came up, so it meets the conflict strategy the way it would for real.
+fn plan_insert<T: Serialize>(
+    summary: &mut ImportSummary,
+    planned_ids: &mut Vec<i32>,
+    content_type: &str,
+    record_id: Option<i32>,
+    record: &T,
+    on_conflict: OnConflict,
+) -> Result<(), ImportError> {
+    let (planned, inserted) = match record_id {
+        Some(record_id) if planned_ids.contains(&record_id) => match on_conflict {
+            OnConflict::Skip => {
+                summary.skipped.push((record_id.to_string(), String::from("already exists")));
+                return Ok(());
+            }
+            OnConflict::Fail => return Err(ImportError::Conflict(format!("{} {} already exists", content_type, record_id))),
+            OnConflict::NewId => (format!("new (from {})", record_id), true),
+            OnConflict::Overwrite => (record_id.to_string(), false),
+        },
+        Some(record_id) => {
+            planned_ids.push(record_id);
+            (record_id.to_string(), true)
+        }
+        None => (String::from("new"), true),
+    };
+    summary.changes.push((planned.clone(), insert_changes(record)));
+    if inserted {
+        summary.inserted.push(planned);
+    } else {
+        summary.updated.push(planned);
+    }
+    Ok(())
+}
+
+fn insert_post_record(connection: &PgConnection, record: &PostRecord, post_id: Option<i32>) -> QueryResult<Post> {
+    diesel::insert_into(posts::table)
+        .values((
+            post_id.map(|post_id| posts::id.eq(post_id)),
+            posts::title.eq(&record.title),
+            posts::body.eq(&record.body),
+            record.time.map(|time| posts::time.eq(time)),
+            record.tags.as_ref().map(|tags| posts::tags.eq(tags)),
+            record.summary.as_ref().map(|summary| posts::summary.eq(summary)),
+            record.version.map(|version| posts::version.eq(version)),
+            record.updated.map(|updated| posts::updated.eq(updated)),
+            record.slug.as_ref().map(|slug| posts::slug.eq(slug)),
+        ))
+        .get_result(connection)
+}
+
+fn insert_link_record(connection: &PgConnection, record: &LinkRecord, link_id: Option<i32>) -> QueryResult<Link> {
+    diesel::insert_into(links::table)
+        .values((
+            link_id.map(|link_id| links::id.eq(link_id)),
+            links::text.eq(&record.text),
+            links::title.eq(&record.title),
+            links::url.eq(&record.url),
+            links::tags.eq(record.tags.as_deref().unwrap_or("")),
+            record.time.map(|time| links::time.eq(time)),
+            record.version.map(|version| links::version.eq(version)),
+            record.updated.map(|updated| links::updated.eq(updated)),
+        ))
+        .get_result(connection)
+}
+
+// An overwrite with an older version than the database would throw away newer edits.
+fn stale_reason(record_version: Option<i32>, existing_version: i32) -> Option<String> {
+    match record_version {
+        Some(record_version) if record_version < existing_version => Some(format!(
+            "stale, version {} is older than version {} in the database", record_version, existing_version
+        )),
+        _ => None,
+    }
+}
+
+// An overwrite is the next version, the one it replaces is kept as a revision.  A file that is
+// ahead of the database doesn't skip versions, that would leave a gap in the revisions.
+fn next_version(existing_version: i32) -> i32 {
+    existing_version + 1
+}
+
+// Inserting an explicit id doesn't move the serial sequence, move it past the id right away so
+// the next insert without an id doesn't collide with it.  The sequence never moves back.
+fn move_id_sequence_past(connection: &PgConnection, table: &str, id: i32) -> QueryResult<usize> {
+    diesel::sql_query(format!(
+        "SELECT setval(sequence, GREATEST($1, COALESCE(pg_sequence_last_value(sequence), 1))) \
+         FROM (SELECT pg_get_serial_sequence('{}', 'id')::regclass AS sequence) serial",
+        table
+    )).bind::<diesel::sql_types::Integer, _>(id).execute(connection)
+}
@@ -1,5 +1,22 @@
 pub mod schema;
 pub mod models;
+pub mod import;
+pub mod markdown;
+pub mod wxr;
+pub mod output;
+pub mod validate;
+pub mod editor;
+pub mod drafts;
+pub mod config;
+pub mod migrations;
+pub mod doctor;
+pub mod bulk;
+pub mod tags;
+pub mod linkcheck;
+pub mod pageinfo;
+pub mod bookmarks;
+pub mod systemdata;
+pub mod menus;
 
 #[macro_use]
 extern crate diesel;
@@ -11,8 +28,9 @@ use diesel::prelude::*;
 use diesel::pg::PgConnection;
 use dotenv::dotenv;
 extern crate dirs;
-use std::path::Path;
 use std::env;
+use std::cell::RefCell;
+use std::rc::Rc;
 use self::models::{
     Node,
     Post,
@@ -35,8 +53,9 @@ use self::models::{
 
 /// Create a database connection.
 ///
-/// Order of operations here is: 1, environment variables first; 2, local .env file from the
-/// execution directory; 3, a .env file in ~/config/N4/.env
+/// Order of operations here is: 1, the database_url of the site profile picked with --site,
+/// N4_PROFILE or the config.toml default; 2, environment variables; 3, local .env file from the
+/// execution directory; 4, a .env file in ~/config/N4/.env
 ///
 /// Does not use pools, so this is not suitable for prod connections.
 ///
@@ -48,29 +67,69 @@ use self::models::{
 /// }
 /// ```
 pub fn establish_connection() -> PgConnection {
-    match dotenv() {
-        Ok(_) => {}
-        Err(_) => {
-            let config_dir = format!("{}/N4/.env", dirs::config_dir()
-                .unwrap()
-                .display()
-                .to_string()
-            );
-            println!("{}", config_dir);  // @TODO remove this at some point in time.
-            match dotenv::from_path(Path::new(&config_dir)) {
-                Ok(_) => {}
-                Err(e) => {
-                    println!("Environment variables not loaded, error.   N4 expects a environment variable of N4_DATABASE_URL or a .dotenv file in the current dir or the local config dir of ~/.config/N4/.env");
-                    panic!(e);
-                }
-            }
+    PgConnection::establish(&database_url())
+        .expect("Error connecting to database.")
+}
+
+/// The database URL to connect to, see establish_connection for where it comes from.
+pub fn database_url() -> String {
+    let profile = config::active_profile().unwrap_or_else(|e| panic!("{}", e));
+    match profile {
+        Some((_, config::Profile { database_url: Some(database_url), .. })) => database_url,
+        Some((name, _)) => panic!("The {} profile has no database_url, set one with \"nautilus config set database_url <URL>\"", name),
+        None => {
+            load_env();
+            env::var("N4_DATABASE_URL")
+                .expect("DATABASE_URL must be set!  N4 expects a site profile in ~/.config/N4/config.toml, an environment variable of N4_DATABASE_URL or a .dotenv file in the current dir or the local config dir of ~/.config/N4/.env")
         }
     }
+}
 
-    let database_url = env::var("N4_DATABASE_URL")
-        .expect("DATABASE_URL must be set!  N4 expects a environment variable of N4_DATABASE_URL or a .dotenv file in the current dir or the local config dir of ~/.config/N4/.env");
-    PgConnection::establish(&database_url)
-        .expect("Error connecting to database.")
+/// Load the config into environment variables.
+///
+/// Variables already set win, then a local .env file from the execution directory, then a .env
+/// file in ~/config/N4/.env
+pub fn load_env() {
+    if dotenv().is_err() {
+        if let Some(config_dir) = dirs::config_dir() {
+            let _ = dotenv::from_path(config_dir.join("N4").join(".env"));
+        }
+    }
+}
+
+thread_local! {
+    static SHARED_CONNECTION: RefCell<Option<Rc<PgConnection>>> = RefCell::new(None);
+}
+
+/// The database connection shared by the library functions on this thread.
+///
+/// The connection is established the first time it's needed and then reused, so a long running
+/// program (like the REPL) doesn't reconnect for every query.  A database schema older than this
+/// build expects is refused when connecting (see "nautilus db migrate").
+///
+/// ```
+/// use nautilus::*;
+///
+/// fn shared_connection_test() {
+///   let connection = shared_connection();
+///   let same_connection = shared_connection();
+/// }
+/// ```
+pub fn shared_connection() -> Rc<PgConnection> {
+    SHARED_CONNECTION.with(|shared| {
+        shared.borrow_mut()
+            .get_or_insert_with(|| {
+                let connection = establish_connection();
+                migrations::check_schema(&connection).unwrap_or_else(|e| panic!("{}", e));
+                Rc::new(connection)
+            })
+            .clone()
+    })
+}
+
+/// Drop the shared database connection, the next query connects again (to a newly picked site).
+pub fn reset_shared_connection() {
+    SHARED_CONNECTION.with(|shared| shared.borrow_mut().take());
 }
 
 /// Create a node
@@ -81,34 +140,35 @@ pub fn establish_connection() -> PgConnection {
 pub fn _create_node() -> Node {
     use schema::nodes;
 
-    let connection = establish_connection();
+    let connection = shared_connection();
 
     let new_node = NewNode::new();
 
     diesel::insert_into(nodes::table)
         .values(&new_node)
-        .get_result(&connection)
+        .get_result(&*connection)
         .expect("Error creating new node")
 }
 
 pub fn _update_new_node_article(node: Node, article: &Post) -> Node {
-    let connection = establish_connection();
+    let connection = shared_connection();
 
     let _node = Node {
-        child: article.id,
+        child: Some(article.id),
+        child_content_type: String::from("post"),
         ..node
     };
-    diesel::update(&_node).set(&_node).get_result(&connection).unwrap()
+    diesel::update(&_node).set(&_node).get_result(&*connection).unwrap()
 }
 
 pub fn create_node_article(content: &Post) -> Post {
     use schema::posts;
 
-    let connection = establish_connection();
+    let connection = shared_connection();
 
     diesel::insert_into(posts::table)
         .values(content)
-        .get_result(&connection)
+        .get_result(&*connection)
         .expect("Error saving new Article to DB")
 }
 
@@ -120,7 +180,7 @@ pub fn create_node_article(content: &Post) -> Post {
 pub fn _save_node_content(node: Node, content: Content) {
     match content {
         PostContent(_post) => {
-            if node.child >= 1 {
+            if node.child.is_some() {
                 // Update the content
                 println!("Update not supported yet")
                 // Create revision
@@ -131,7 +191,7 @@ pub fn _save_node_content(node: Node, content: Content) {
                 // Create new node
                 // Create new content row with node.id as parent value
                 let _content = Post {
-                    parent: node.id,
+                    parent: Some(node.id),
                     .._post
                 };
                 // println!("{:#?}", _content);
@@ -160,6 +220,7 @@ pub fn _save_node_content(node: Node, content: Content) {
 ///     body: "Something",
 ///     summary: "Something",
 ///     tags: "This, That",
+///     slug: "something",
 ///   };
 ///
 ///   let newpost = create_post(&thingy);
@@ -170,17 +231,101 @@ pub fn _save_node_content(node: Node, content: Content) {
 pub fn create_post(content: &NewPost) -> Post {
     use schema::posts;
 
-    let connection = establish_connection();
+    let connection = shared_connection();
 
     diesel::insert_into(posts::table)
         .values(content)
-        .get_result(&connection)
+        .get_result(&*connection)
         .expect("Error saving new post")
 }
 
+/// Enter a NewPost with an existing creation time, like a post brought over from another blog.
+///
+/// The updated time is set to the same value so the post doesn't look edited on import.
+///
+/// ```
+/// use nautilus::*;
+/// use nautilus::models::{NewPost};
+///
+/// extern crate chrono;
+/// use chrono::{NaiveDate};
+///
+/// fn post_something_old() {
+///   let thingy = NewPost {
+///     title: "Something",
+///     body: "Something",
+///     summary: "Something",
+///     tags: "This, That",
+///     slug: "something",
+///   };
+///
+///   let oldpost = create_post_with_time(&thingy, NaiveDate::from_ymd(2016, 7, 8).and_hms(9, 10, 11));
+///   println!("{:?}", oldpost)
+///
+/// }
+/// ```
+pub fn create_post_with_time(content: &NewPost, post_time: chrono::NaiveDateTime) -> Post {
+    use schema::posts::dsl::*;
+
+    let connection = shared_connection();
+
+    diesel::insert_into(posts)
+        .values((content, time.eq(post_time), updated.eq(post_time)))
+        .get_result(&*connection)
+        .expect("Error saving new post")
+}
+
+/// Make a URL slug from a title, lowercase ascii letters and numbers separated by single dashes.
+///
+/// ```
+/// use nautilus::*;
+///
+/// assert_eq!(slugify("Rustyline is pretty slick!"), "rustyline-is-pretty-slick");
+/// ```
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+    for character in title.chars() {
+        if character.is_ascii_alphanumeric() {
+            slug.push(character.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// The tags in a comma separated tags field, trimmed and without empty entries.
+///
+/// ```
+/// use nautilus::*;
+///
+/// assert_eq!(split_tags(" rust,, cli , "), vec!["rust", "cli"]);
+/// ```
+pub fn split_tags(tags: &str) -> Vec<&str> {
+    tags.split(',')
+        .map(|tag| tag.trim())
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+/// Check a comma separated tags field for a tag, ignoring case.
+///
+/// ```
+/// use nautilus::*;
+///
+/// assert!(has_tag("Rust, cli", "rust"));
+/// assert!(!has_tag("rustyline", "rust"));
+/// ```
+pub fn has_tag(tags: &str, tag: &str) -> bool {
+    split_tags(tags).iter().any(|existing| existing.eq_ignore_ascii_case(tag.trim()))
+}
 
 /// Update an existing post
 ///
+/// The post is saved as the next version and the version it replaces is kept as a revision.  The
+/// version of `content` is the one the edit was made from, returns the number of posts updated, 0
+/// when the post is gone, in the trash or saved again since.
+///
 /// ```
 /// use nautilus::*;
 /// use nautilus::models::{Post};
@@ -198,9 +343,11 @@ pub fn create_post(content: &NewPost) -> Post {
 ///     body: String::from("Something"),
 ///     summary: String::from("Something else"),
 ///     tags: String::from("This, That"),
-///     parent: 1,
+///     parent: Some(1),
 ///     updated: NaiveDate::from_ymd(2016, 7, 8).and_hms(9, 10, 11),
 ///     version: 2,
+///     slug: String::from("somethin-else"),
+///     trashed: None,
 ///   };
 ///
 ///   let newpost = update_post(&thingy);
@@ -209,10 +356,36 @@ pub fn create_post(content: &NewPost) -> Post {
 /// }
 /// ```
 pub fn update_post(content: &Post) -> QueryResult<usize>{
-    let connection = establish_connection();
-
-    diesel::update(content).set(content).execute(&connection)
-
+    use schema::{posts, post_revisions};
+    let connection = shared_connection();
+
+    connection.transaction(|| {
+        let current = posts::table.find(content.id)
+            .filter(posts::trashed.is_null())
+            .filter(posts::version.eq(content.version))
+            .get_result::<Post>(&*connection)
+            .optional()?;
+        let current = match current {
+            Some(current) => current,
+            None => return Ok(0),
+        };
+        diesel::insert_into(post_revisions::table)
+            .values(&PostRevision::from(&current))
+            .execute(&*connection)?;
+        diesel::update(&current)
+            .set((
+                posts::title.eq(&content.title),
+                posts::body.eq(&content.body),
+                posts::time.eq(content.time),
+                posts::tags.eq(&content.tags),
+                posts::summary.eq(&content.summary),
+                posts::slug.eq(&content.slug),
+                posts::parent.eq(content.parent),
+                posts::version.eq(current.version + 1),
+                posts::updated.eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(&*connection)
+    })
 }
 
 /// Read a post by post id
@@ -228,11 +401,12 @@ pub fn update_post(content: &Post) -> QueryResult<usize>{
 pub fn read_post(post_id: i32) -> Post{
     use schema::posts::dsl::*;
 
-    let connection = establish_connection();
+    let connection = shared_connection();
 
     posts.filter(id.eq(post_id))
+        .filter(trashed.is_null())
         .limit(1)
-        .get_result::<Post>(&connection)
+        .get_result::<Post>(&*connection)
         .expect("Error loading post by that ID")
 }
 
@@ -251,11 +425,12 @@ pub fn read_post(post_id: i32) -> Post{
 pub fn read_all_posts() -> Vec<Post> {
     use schema::posts::dsl::*;
 
-    let connection = establish_connection();
+    let connection = shared_connection();
 
     posts
+        .filter(trashed.is_null())
         .order(id.asc())
-        .load::<Post>(&connection)
+        .load::<Post>(&*connection)
         .expect("Error loading posts")
 }
 
@@ -274,12 +449,13 @@ pub fn read_all_posts() -> Vec<Post> {
 pub fn read_some_posts(limit_value: i64) -> Vec<Post> {
     use schema::posts::dsl::*;
 
-    let connection = establish_connection();
+    let connection = shared_connection();
 
     posts
+        .filter(trashed.is_null())
         .order(id.desc())
         .limit(limit_value)
-        .load::<Post>(&connection)
+        .load::<Post>(&*connection)
         .expect("Error loading posts")
 }
 
@@ -299,15 +475,16 @@ pub fn read_some_posts(limit_value: i64) -> Vec<Post> {
 pub fn read_posts_by_filter_limit(filter_value: String, limit_value: i64) -> Vec<Post> {
     use schema::posts::dsl::*;
 
-    let connection = establish_connection();
+    let connection = shared_connection();
 
     let real_filter_value = format!("%%{}%%", filter_value);
 
     posts
         .filter(tags.like(&real_filter_value))
+        .filter(trashed.is_null())
         .limit(limit_value)
         .order(id.desc())
-        .load::<Post>(&connection)
+        .load::<Post>(&*connection)
         .expect("Error loading posts")
 }
 
@@ -315,31 +492,67 @@ pub fn read_posts_by_filter_limit(filter_value: String, limit_value: i64) -> Vec
 // pub fn publish_post(post_id: i32) -> Post {
 //     use schema::posts::dsl::{posts, published};
 //
-//     let connection = establish_connection();
+//     let connection = shared_connection();
 //
 //     diesel::update(posts.find(post_id))
 //         .set(published.eq(true))
-//         .get_result::<Post>(&connection)
+//         .get_result::<Post>(&*connection)
 //         .expect("Unable to find post number")
 // }
 
 /// Delete a post by post id
 ///
+/// The post is moved to the trash with its revisions, see restore_post and purge_trash.  Returns
+/// the number of posts trashed (0 or 1).
+///
 /// ```
 /// use nautilus::*;
 ///
 /// fn delete_a_post() {
-///   delete_post(1)
+///   delete_post(1);
+/// }
+/// ```
+pub fn delete_post(post_id: i32) -> usize {
+    use schema::posts::dsl::*;
+
+    let connection = shared_connection();
+
+    diesel::update(posts.filter(id.eq(post_id)).filter(trashed.is_null()))
+        .set(trashed.eq(chrono::Utc::now().naive_utc()))
+        .execute(&*connection)
+        .expect("Error deleting post")
+}
+
+/// Take a post back out of the trash, returns the number of posts restored (0 or 1)
+///
+/// ```
+/// use nautilus::*;
+///
+/// fn restore_a_post() {
+///   restore_post(1).unwrap();
 /// }
 /// ```
-pub fn delete_post(post_id: i32) {
+pub fn restore_post(post_id: i32) -> QueryResult<usize> {
     use schema::posts::dsl::*;
 
-    let connection = establish_connection();
+    let connection = shared_connection();
+
+    diesel::update(posts.filter(id.eq(post_id)).filter(trashed.is_not_null()))
+        .set(trashed.eq(None::<chrono::NaiveDateTime>))
+        .execute(&*connection)
+}
 
-    diesel::delete(posts.filter(id.eq(post_id)))
-        .execute(&connection)
-        .expect("Error deleting post");
+/// Read the posts in the trash, most recently trashed first
+pub fn read_trashed_posts() -> Vec<Post> {
+    use schema::posts::dsl::*;
+
+    let connection = shared_connection();
+
+    posts
+        .filter(trashed.is_not_null())
+        .order(trashed.desc())
+        .load::<Post>(&*connection)
+        .expect("Error loading posts")
 }
 
 /// Read a link by link id
@@ -355,11 +568,12 @@ pub fn delete_post(post_id: i32) {
 pub fn read_link(link_id: i32) -> Link{
     use schema::links::dsl::*;
 
-    let connection = establish_connection();
+    let connection = shared_connection();
 
     links.filter(id.eq(link_id))
+        .filter(trashed.is_null())
         .limit(1)
-        .get_result::<Link>(&connection)
+        .get_result::<Link>(&*connection)
         .expect("Error loading post by that ID")
 }
 
@@ -379,11 +593,12 @@ pub fn read_link(link_id: i32) -> Link{
 pub fn read_all_links() -> Vec<Link> {
     use schema::links::dsl::*;
 
-    let connection = establish_connection();
+    let connection = shared_connection();
 
     links
+        .filter(trashed.is_null())
         .order(id.asc())
-        .load::<Link>(&connection)
+        .load::<Link>(&*connection)
         .expect("Error loading links")
 }
 
@@ -403,15 +618,16 @@ pub fn read_all_links() -> Vec<Link> {
 pub fn read_links_by_filter_limit(filter_value: String, limit_value: i64) -> Vec<Link> {
     use schema::links::dsl::*;
 
-    let connection = establish_connection();
+    let connection = shared_connection();
 
     let real_filter_value = format!("%%{}%%", filter_value);
 
     links
         .filter(tags.like(&real_filter_value))
+        .filter(trashed.is_null())
         .limit(limit_value)
         .order(id.desc())
-        .load::<Link>(&connection)
+        .load::<Link>(&*connection)
         .expect("Error loading links")
 }
 
@@ -437,16 +653,55 @@ pub fn read_links_by_filter_limit(filter_value: String, limit_value: i64) -> Vec
 pub fn create_link(content: &NewLink) -> Link {
     use schema::links;
 
-    let connection = establish_connection();
+    let connection = shared_connection();
 
     diesel::insert_into(links::table)
         .values(content)
-        .get_result(&connection)
+        .get_result(&*connection)
+        .expect("Error saving new link")
+}
+
+/// Enter a NewLink with an existing creation time, like a bookmark brought over from a browser.
+///
+/// The updated time is set to the same value so the link doesn't look edited on import.
+///
+/// ```
+/// use nautilus::*;
+/// use nautilus::models::{NewLink};
+///
+/// extern crate chrono;
+/// use chrono::{NaiveDate};
+///
+/// fn link_something_old() {
+///   let thingy = NewLink {
+///     text: "Something",
+///     title: "Something",
+///     url: "https://duckduckgo.com/",
+///     tags: "This, that",
+///   };
+///
+///   let oldlink = create_link_with_time(&thingy, NaiveDate::from_ymd(2016, 7, 8).and_hms(9, 10, 11));
+///   println!("{:?}", oldlink)
+///
+/// }
+/// ```
+pub fn create_link_with_time(content: &NewLink, link_time: chrono::NaiveDateTime) -> Link {
+    use schema::links::dsl::*;
+
+    let connection = shared_connection();
+
+    diesel::insert_into(links)
+        .values((content, time.eq(link_time), updated.eq(link_time)))
+        .get_result(&*connection)
         .expect("Error saving new link")
 }
 
 /// Update an existing link
 ///
+/// The link is saved as the next version and the version it replaces is kept as a revision.  The
+/// version of `content` is the one the edit was made from, returns the number of links updated, 0
+/// when the link is gone, in the trash or saved again since.
+///
 /// ```
 /// use nautilus::*;
 /// use nautilus::models::{Link};
@@ -464,9 +719,10 @@ pub fn create_link(content: &NewLink) -> Link {
 ///     title: String::from("Something"),
 ///     url: String::from("Something else"),
 ///     tags: String::from("This, That"),
-///     parent: 1,
+///     parent: Some(1),
 ///     updated: NaiveDate::from_ymd(2016, 7, 8).and_hms(9, 10, 11),
 ///     version: 2,
+///     trashed: None,
 ///   };
 ///
 ///   let uplink = update_link(&thingy);
@@ -475,29 +731,82 @@ pub fn create_link(content: &NewLink) -> Link {
 /// }
 /// ```
 pub fn update_link(content: &Link) -> QueryResult<usize> {
-    let connection = establish_connection();
-
-    diesel::update(content).set(content).execute(&connection)
-
+    use schema::{links, link_revisions};
+    let connection = shared_connection();
+
+    connection.transaction(|| {
+        let current = links::table.find(content.id)
+            .filter(links::trashed.is_null())
+            .filter(links::version.eq(content.version))
+            .get_result::<Link>(&*connection)
+            .optional()?;
+        let current = match current {
+            Some(current) => current,
+            None => return Ok(0),
+        };
+        diesel::insert_into(link_revisions::table)
+            .values(&LinkRevision::from(&current))
+            .execute(&*connection)?;
+        diesel::update(&current)
+            .set((
+                links::text.eq(&content.text),
+                links::title.eq(&content.title),
+                links::url.eq(&content.url),
+                links::tags.eq(&content.tags),
+                links::time.eq(content.time),
+                links::parent.eq(content.parent),
+                links::version.eq(current.version + 1),
+                links::updated.eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(&*connection)
+    })
 }
 
 /// Delete a link by link id
 ///
+/// The link is moved to the trash with its revisions, see restore_link and purge_trash.  Returns
+/// the number of links trashed (0 or 1).
+///
 /// ```
 /// use nautilus::*;
 ///
-/// fn delete_a_post() {
-///   delete_post(1)
+/// fn delete_a_link() {
+///   delete_link(1);
 /// }
 /// ```
-pub fn delete_link(link_id: i32) {
+pub fn delete_link(link_id: i32) -> usize {
     use schema::links::dsl::*;
 
-    let connection = establish_connection();
+    let connection = shared_connection();
 
-    diesel::delete(links.filter(id.eq(link_id)))
-        .execute(&connection)
-        .expect("Error deleting link");
+    diesel::update(links.filter(id.eq(link_id)).filter(trashed.is_null()))
+        .set(trashed.eq(chrono::Utc::now().naive_utc()))
+        .execute(&*connection)
+        .expect("Error deleting link")
+}
+
+/// Take a link back out of the trash, returns the number of links restored (0 or 1)
+pub fn restore_link(link_id: i32) -> QueryResult<usize> {
+    use schema::links::dsl::*;
+
+    let connection = shared_connection();
+
+    diesel::update(links.filter(id.eq(link_id)).filter(trashed.is_not_null()))
+        .set(trashed.eq(None::<chrono::NaiveDateTime>))
+        .execute(&*connection)
+}
+
+/// Read the links in the trash, most recently trashed first
+pub fn read_trashed_links() -> Vec<Link> {
+    use schema::links::dsl::*;
+
+    let connection = shared_connection();
+
+    links
+        .filter(trashed.is_not_null())
+        .order(trashed.desc())
+        .load::<Link>(&*connection)
+        .expect("Error loading links")
 }
 
 /// Read all system entries
@@ -515,14 +824,16 @@ pub fn delete_link(link_id: i32) {
 /// ```
 pub fn read_all_system() -> Vec<System> {
     use schema::system::dsl::*;
-    let connection = establish_connection();
+    let connection = shared_connection();
 
     system
-        .load::<System>(&connection)
+        .filter(trashed.is_null())
+        .order(key.asc())
+        .load::<System>(&*connection)
         .expect("Error loading system")
 }
 
-/// Read a system entries by system key value
+/// Read a system entry by its exact key, see read_system_by_prefix for several entries.
 ///
 /// ```
 /// use nautilus::*;
@@ -535,15 +846,105 @@ pub fn read_all_system() -> Vec<System> {
 pub fn read_system(system_key: String) -> System {
     use schema::system::dsl::*;
 
-    let connection = establish_connection();
+    let connection = shared_connection();
 
-    system.filter(key.like(&system_key))
-        .limit(1)
-        .get_result::<System>(&connection)
+    system.filter(key.eq(&system_key))
+        .filter(trashed.is_null())
+        .get_result::<System>(&*connection)
         .expect("System key error")
 }
 
+/// Read the system entries whose keys start with a prefix, in key order.  The prefix is taken as
+/// it is, a % or _ in it is not a wildcard.
+///
+/// ```
+/// use nautilus::*;
+///
+/// fn read_the_site_settings() {
+///   for entry in read_system_by_prefix("site.") {
+///     println!("{} = {}", entry.key, entry.data);
+///   }
+/// }
+/// ```
+pub fn read_system_by_prefix(prefix: &str) -> Vec<System> {
+    use schema::system::dsl::*;
+
+    let connection = shared_connection();
+
+    system.filter(key.like(format!("{}%", escape_like(prefix))))
+        .filter(trashed.is_null())
+        .order(key.asc())
+        .load::<System>(&*connection)
+        .expect("Error loading system")
+}
+
+/// Read the system entries in a namespace, "site" or "site.*" reads site.title, site.theme and so
+/// on (but not the site key itself).
+///
+/// ```
+/// use nautilus::*;
+///
+/// fn read_the_routes() {
+///   for entry in read_system_namespace("routes.*") {
+///     println!("{} = {}", entry.key, entry.data);
+///   }
+/// }
+/// ```
+pub fn read_system_namespace(namespace: &str) -> Vec<System> {
+    let namespace = namespace.trim_end_matches('*').trim_end_matches('.');
+    read_system_by_prefix(&format!("{}.", namespace))
+}
+
+/// Escape the LIKE wildcards (and the escape character) in text matched as it is.
+///
+/// ```
+/// use nautilus::*;
+///
+/// assert_eq!(escape_like("100%_sure\\"), "100\\%\\_sure\\\\");
+/// ```
+pub fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Read the value of a system entry as a type, like a struct of the site settings.  A key that's
+/// gone or in the trash is a SystemReadError::Missing, not a panic.
+///
+/// ```
+/// use nautilus::*;
+/// use nautilus::systemdata::SystemReadError;
+/// use serde_derive::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct Site {
+///   title: String,
+///   posts_per_page: u32,
+/// }
+///
+/// fn read_the_site_settings() {
+///   match read_system_as::<Site>("site") {
+///     Ok(site) => println!("{} with {} posts a page", site.title, site.posts_per_page),
+///     Err(SystemReadError::Missing(_)) => println!("The site isn't set up yet"),
+///     Err(e) => println!("{}", e),
+///   }
+/// }
+/// ```
+pub fn read_system_as<T: serde::de::DeserializeOwned>(system_key: &str) -> Result<T, systemdata::SystemReadError> {
+    use schema::system;
+
+    let connection = shared_connection();
+    let data = system::table.find(system_key)
+        .filter(system::trashed.is_null())
+        .select(system::data)
+        .get_result::<serde_json::Value>(&*connection)
+        .optional()?
+        .ok_or_else(|| systemdata::SystemReadError::Missing(String::from(system_key)))?;
+    Ok(serde_json::from_value(data)?)
+}
+
 /// Enter a NewSystem struct into the database (tracks closely to System without the auto fields).
+/// A key already in use or in the trash is refused.
 ///
 /// ```
 /// use nautilus::*;
@@ -552,25 +953,39 @@ pub fn read_system(system_key: String) -> System {
 /// fn system_something() {
 ///   let thingy = NewSystem {
 ///     key: "This",
-///     data: "That"
+///     data: &serde_json::json!("That"),
 ///   };
 ///
-///   let newsys = create_system(&thingy);
+///   let newsys = create_system(&thingy).unwrap();
 ///   println!("{:?}", newsys)
 ///
 /// }
 /// ```
-pub fn create_system(content: &NewSystem) -> System {
+pub fn create_system(content: &NewSystem) -> Result<System, String> {
     use schema::system;
 
-    let connection = establish_connection();
+    let connection = shared_connection();
+    let existing = system::table.find(content.key)
+        .get_result::<System>(&*connection)
+        .optional()
+        .map_err(|e| format!("Could not read the system entries: {}", e))?;
+    match existing {
+        Some(existing) if existing.trashed.is_some() => {
+            return Err(format!("{} is in the trash, purge or restore it first", content.key));
+        }
+        Some(_) => return Err(format!("{} already exists, edit it instead", content.key)),
+        None => {}
+    }
     diesel::insert_into(system::table)
         .values(content)
-        .get_result(&connection)
-        .expect("Error saving new link")
+        .get_result(&*connection)
+        .map_err(|e| format!("Error saving new system entry: {}", e))
 }
 
-/// Update an existing system
+/// Update an existing system entry, the one with the same key.  The key itself is never changed
+/// here, see rename_system.  The entry is saved as the next version and the version it replaces is
+/// kept as a revision.  The version of `content` is the one the edit was made from, returns the
+/// number of entries updated, 0 when the key is gone, in the trash or saved again since.
 ///
 /// ```
 /// use nautilus::*;
@@ -584,10 +999,11 @@ pub fn create_system(content: &NewSystem) -> System {
 /// fn update_some_system() {
 ///   let thingy = System {
 ///     key: String::from("routes"),
-///     data: String::from("/post/"),
+///     data: serde_json::json!("/post/"),
 ///     time: NaiveDate::from_ymd(2016, 7, 8).and_hms(9, 10, 11),
 ///     version: 2,
 ///     updated: NaiveDate::from_ymd(2016, 7, 8).and_hms(9, 10, 11),
+///     trashed: None,
 ///   };
 ///
 ///   let upsys = update_system(&thingy);
@@ -596,38 +1012,216 @@ pub fn create_system(content: &NewSystem) -> System {
 /// }
 /// ```
 pub fn update_system(content: &System) -> QueryResult<usize> {
-    use schema::system;
-    let connection = establish_connection();
+    use schema::{system, system_revisions};
+    let connection = shared_connection();
+
+    connection.transaction(|| {
+        let current = system::table.find(&content.key)
+            .filter(system::trashed.is_null())
+            .filter(system::version.eq(content.version))
+            .get_result::<System>(&*connection)
+            .optional()?;
+        let current = match current {
+            Some(current) => current,
+            None => return Ok(0),
+        };
+        diesel::insert_into(system_revisions::table)
+            .values(&SystemRevision::from(&current))
+            .execute(&*connection)?;
+        diesel::update(system::table.find(&current.key))
+            .set((
+                system::data.eq(&content.data),
+                system::time.eq(content.time),
+                system::version.eq(current.version + 1),
+                system::updated.eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(&*connection)
+    })
+}
+
+/// Give a system entry a new key.  Its revisions and its JSON Schema go with it, so its history
+/// carries on under the new key.
+///
+/// A key already in use, in the trash or with revisions left from an earlier entry is refused, and
+/// so is a value that doesn't match the schema the new key already has.
+///
+/// ```
+/// use nautilus::*;
+///
+/// fn rename_the_site_title() {
+///   let renamed = rename_system("site_title", "site.title").unwrap();
+///   println!("{} is at version {}", renamed.key, renamed.version);
+/// }
+/// ```
+pub fn rename_system(system_key: &str, new_key: &str) -> Result<System, String> {
+    use schema::{system, system_revisions, system_schemas};
+
+    let new_key = new_key.trim();
+    if new_key.is_empty() {
+        return Err(String::from("A system key can't be empty"));
+    }
+    if new_key == system_key {
+        return Err(format!("{} already has that key", system_key));
+    }
+    let connection = shared_connection();
+    let read_error = |e: diesel::result::Error| format!("Could not read the system entries: {}", e);
+
+    let entry = system::table.find(system_key)
+        .filter(system::trashed.is_null())
+        .get_result::<System>(&*connection)
+        .optional()
+        .map_err(read_error)?
+        .ok_or_else(|| format!("No system key {}", system_key))?;
+    let existing = system::table.find(new_key)
+        .get_result::<System>(&*connection)
+        .optional()
+        .map_err(read_error)?;
+    match existing {
+        Some(existing) if existing.trashed.is_some() => {
+            return Err(format!("{} is in the trash, purge or restore it first", new_key));
+        }
+        Some(_) => return Err(format!("{} already exists, rename or delete it first", new_key)),
+        None => {}
+    }
+    let old_revisions: i64 = system_revisions::table
+        .filter(system_revisions::key.eq(new_key))
+        .count()
+        .get_result(&*connection)
+        .map_err(read_error)?;
+    if old_revisions > 0 {
+        return Err(format!("{} still has revisions from an earlier entry", new_key));
+    }
+    let new_key_schema = systemdata::read_system_schema(new_key).map_err(read_error)?;
+    if new_key_schema.is_some() {
+        systemdata::check_system_data(new_key, &entry.data)?;
+    }
 
-    diesel::update(system::table).set(content).execute(&connection)
+    connection.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::update(system_revisions::table.filter(system_revisions::key.eq(system_key)))
+            .set(system_revisions::key.eq(new_key))
+            .execute(&*connection)?;
+        if new_key_schema.is_none() {
+            diesel::update(system_schemas::table.find(system_key))
+                .set(system_schemas::key.eq(new_key))
+                .execute(&*connection)?;
+        }
+        diesel::update(system::table.find(system_key).filter(system::version.eq(entry.version)))
+            .set((system::key.eq(new_key), system::updated.eq(chrono::Utc::now().naive_utc())))
+            .get_result::<System>(&*connection)
+    }).map_err(|e| format!("Could not rename {} to {}: {}", system_key, new_key, e))
 }
 
-/// Delete a link by link id
+/// Delete a system entry by key
+///
+/// The entry is moved to the trash with its revisions, see restore_system and purge_trash.
+/// Returns the number of entries trashed (0 or 1).
 ///
 /// ```
 /// use nautilus::*;
 ///
 /// fn delete_a_system() {
-///   delete_system("routes")
+///   delete_system("routes");
 /// }
 /// ```
-pub fn delete_system(system_key: &str) {
+pub fn delete_system(system_key: &str) -> usize {
     use schema::system::dsl::*;
-    let connection = establish_connection();
+    let connection = shared_connection();
 
-    diesel::delete(system.filter(key.like(system_key)))
-        .execute(&connection)
-        .expect("Error deleting system");
+    diesel::update(system.filter(key.eq(system_key)).filter(trashed.is_null()))
+        .set(trashed.eq(chrono::Utc::now().naive_utc()))
+        .execute(&*connection)
+        .expect("Error deleting system")
+}
+
+/// Take a system entry back out of the trash, returns the number of entries restored (0 or 1)
+pub fn restore_system(system_key: &str) -> QueryResult<usize> {
+    use schema::system::dsl::*;
+    let connection = shared_connection();
+
+    diesel::update(system.filter(key.eq(system_key)).filter(trashed.is_not_null()))
+        .set(trashed.eq(None::<chrono::NaiveDateTime>))
+        .execute(&*connection)
+}
+
+/// Read the system entries in the trash, most recently trashed first
+pub fn read_trashed_system() -> Vec<System> {
+    use schema::system::dsl::*;
+    let connection = shared_connection();
+
+    system
+        .filter(trashed.is_not_null())
+        .order(trashed.desc())
+        .load::<System>(&*connection)
+        .expect("Error loading system")
+}
+
+/// What purge_trash removed for good.
+#[derive(Debug, Default)]
+pub struct PurgedTrash {
+    pub posts: Vec<i32>,
+    pub links: Vec<i32>,
+    pub system: Vec<String>,
+}
+
+/// Remove everything trashed before a time for good, with its revisions and nodes, in one
+/// transaction.
+///
+/// ```
+/// use nautilus::*;
+///
+/// fn empty_last_months_trash() {
+///   let month_ago = chrono::Utc::now().naive_utc() - chrono::Duration::days(30);
+///   let purged = purge_trash(month_ago).unwrap();
+///   println!("Purged {} posts", purged.posts.len());
+/// }
+/// ```
+pub fn purge_trash(trashed_before: chrono::NaiveDateTime) -> QueryResult<PurgedTrash> {
+    use schema::{posts, links, system, nodes, post_revisions, link_revisions, node_revisions, system_revisions};
+
+    let connection = shared_connection();
+
+    connection.transaction(|| {
+        let purged = PurgedTrash {
+            posts: posts::table.select(posts::id)
+                .filter(posts::trashed.lt(trashed_before))
+                .order(posts::id.asc())
+                .load(&*connection)?,
+            links: links::table.select(links::id)
+                .filter(links::trashed.lt(trashed_before))
+                .order(links::id.asc())
+                .load(&*connection)?,
+            system: system::table.select(system::key)
+                .filter(system::trashed.lt(trashed_before))
+                .order(system::key.asc())
+                .load(&*connection)?,
+        };
+
+        // The nodes of the content go with it
+        let purged_nodes: Vec<i32> = nodes::table.select(nodes::id)
+            .filter(nodes::child_content_type.eq("post").and(nodes::child.eq_any(&purged.posts))
+                .or(nodes::child_content_type.eq("link").and(nodes::child.eq_any(&purged.links))))
+            .load(&*connection)?;
+        diesel::delete(node_revisions::table.filter(node_revisions::id.eq_any(&purged_nodes))).execute(&*connection)?;
+        diesel::delete(nodes::table.filter(nodes::id.eq_any(&purged_nodes))).execute(&*connection)?;
+
+        diesel::delete(post_revisions::table.filter(post_revisions::id.eq_any(&purged.posts))).execute(&*connection)?;
+        diesel::delete(posts::table.filter(posts::id.eq_any(&purged.posts))).execute(&*connection)?;
+        diesel::delete(link_revisions::table.filter(link_revisions::id.eq_any(&purged.links))).execute(&*connection)?;
+        diesel::delete(links::table.filter(links::id.eq_any(&purged.links))).execute(&*connection)?;
+        diesel::delete(system_revisions::table.filter(system_revisions::key.eq_any(&purged.system))).execute(&*connection)?;
+        diesel::delete(system::table.filter(system::key.eq_any(&purged.system))).execute(&*connection)?;
+        Ok(purged)
+    })
 }
 
 // Not used yet
 // pub fn publish_link(link_id: i32) -> Link {
 //     use schema::links::dsl::{links, published};
 //
-//     let connection = establish_connection();
+//     let connection = shared_connection();
 //
 //     diesel::update(links.find(link_id))
 //         .set(published.eq(true))
-//         .get_result::<Link>(&connection)
+//         .get_result::<Link>(&*connection)
 //         .expect("Unable to find post number")
 // }
@@ -0,0 +1,238 @@
+// Checking the URLs of the links and the ones in post bodies.  Every URL is requested once, however
+// many places use it, by a few threads at a time.  A HEAD request is tried first and a GET when the
+// server doesn't allow HEAD, redirects are followed and where they end up is kept.  The last check
+// of every URL is saved in the url_checks table.
+
+use diesel::prelude::*;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use crate::{read_all_links, read_all_posts, shared_connection};
+use crate::models::UrlCheck;
+use crate::schema::url_checks;
+
+/// How URLs are checked.
+#[derive(Clone, Copy, Debug)]
+pub struct CheckOptions {
+    /// How many URLs are requested at the same time
+    pub concurrency: usize,
+    /// How long to wait for a server before giving up on a request
+    pub timeout: Duration,
+    /// How many times a URL is tried again after a timeout, a connection error or a server error
+    pub retries: u32,
+}
+
+impl Default for CheckOptions {
+    fn default() -> CheckOptions {
+        CheckOptions {
+            concurrency: 8,
+            timeout: Duration::from_secs(10),
+            retries: 2,
+        }
+    }
+}
+
+impl UrlCheck {
+    /// No response, or an error status.
+    pub fn is_broken(&self) -> bool {
+        self.status.map(|status| status >= 400).unwrap_or(true)
+    }
+
+    /// The URL works, but somewhere else.
+    pub fn is_redirected(&self) -> bool {
+        !self.is_broken() && self.redirect.is_some()
+    }
+}
+
+/// The http and https URLs in a post body, from HTML links and Markdown links, in order and once
+/// each.
+///
+/// ```
+/// use nautilus::linkcheck::extract_urls;
+///
+/// let body = r#"See [the book](https://doc.rust-lang.org/book/ "The Book"), <https://crates.io/>
+/// and <a href="https://example.com/?a=1&amp;b=2">this</a> or [home](/) and [the book](https://doc.rust-lang.org/book/)."#;
+/// assert_eq!(extract_urls(body), vec![
+///   "https://doc.rust-lang.org/book/",
+///   "https://crates.io/",
+///   "https://example.com/?a=1&b=2",
+/// ]);
+/// ```
+pub fn extract_urls(body: &str) -> Vec<String> {
+    let mut found: Vec<(usize, String)> = Vec::new();
+    let lowercase = body.to_ascii_lowercase();
+    for (attribute, _) in lowercase.match_indices("href=") {
+        let value_start = attribute + "href=".len();
+        let quote = match body[value_start..].chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => quote,
+            _ => continue,
+        };
+        if let Some(length) = body[value_start + 1..].find(quote) {
+            let href = &body[value_start + 1..value_start + 1 + length];
+            found.push((attribute, href.replace("&amp;", "&")));
+        }
+    }
+    for (markdown_link, _) in body.match_indices("](") {
+        let target = &body[markdown_link + 2..];
+        let end = target.find(|character: char| character == ')' || character.is_whitespace()).unwrap_or(target.len());
+        found.push((markdown_link, String::from(&target[..end])));
+    }
+    for (autolink, _) in body.match_indices('<') {
+        let target = &body[autolink + 1..];
+        if let Some(end) = target.find('>') {
+            found.push((autolink, String::from(&target[..end])));
+        }
+    }
+    found.sort_by_key(|(position, _)| *position);
+
+    let mut urls: Vec<String> = Vec::new();
+    for (_, url) in found {
+        let url = url.trim();
+        let web = url.starts_with("http://") || url.starts_with("https://");
+        if web && !url.contains(char::is_whitespace) && !urls.iter().any(|seen| seen == url) {
+            urls.push(String::from(url));
+        }
+    }
+    urls
+}
+
+/// Every URL of the links and post bodies outside the trash, with where it's used ("link 3",
+/// "post 5"), in link then post order.
+pub fn content_urls() -> Vec<(String, Vec<String>)> {
+    let mut urls: Vec<(String, Vec<String>)> = Vec::new();
+    let mut used = |url: String, source: String| {
+        match urls.iter_mut().find(|(seen, _)| *seen == url) {
+            Some((_, sources)) => sources.push(source),
+            None => urls.push((url, vec![source])),
+        }
+    };
+    for link in read_all_links() {
+        used(link.url.trim().to_string(), format!("link {}", link.id));
+    }
+    for post in read_all_posts() {
+        for url in extract_urls(&post.body) {
+            used(url, format!("post {}", post.id));
+        }
+    }
+    urls
+}
+
+/// Check one URL with an agent from check_agent.
+pub fn check_url(agent: &ureq::Agent, url: &str, retries: u32) -> UrlCheck {
+    let mut attempt = 0;
+    loop {
+        let result = match agent.head(url).call() {
+            Err(ureq::Error::Status(405, _)) | Err(ureq::Error::Status(501, _)) => agent.get(url).call(),
+            result => result,
+        };
+        let (status, final_url, error) = match result {
+            Ok(response) => (Some(response.status()), Some(String::from(response.get_url())), None),
+            Err(ureq::Error::Status(status, response)) => (Some(status), Some(String::from(response.get_url())), None),
+            Err(ureq::Error::Transport(transport)) => (None, None, Some(transport.to_string())),
+        };
+        let retry = status.map(|status| status == 429 || status >= 500).unwrap_or(true);
+        if retry && attempt < retries {
+            attempt += 1;
+            thread::sleep(Duration::from_millis(500 * u64::from(attempt)));
+            continue;
+        }
+        return UrlCheck {
+            url: String::from(url),
+            status: status.map(i32::from),
+            redirect: final_url.filter(|final_url| !same_url(final_url, url)),
+            error,
+            checked: chrono::Utc::now().naive_utc(),
+        };
+    }
+}
+
+/// The HTTP agent URLs are checked with.
+pub fn check_agent(options: &CheckOptions) -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(options.timeout)
+        .redirects(10)
+        .user_agent(concat!("nautilus/", env!("CARGO_PKG_VERSION"), " link checker"))
+        .build()
+}
+
+/// Check URLs concurrently, the checks come back in the order of the URLs.
+///
+/// ```
+/// use nautilus::linkcheck::{check_urls, CheckOptions};
+/// use std::io::{Read, Write};
+/// use std::net::TcpListener;
+///
+/// // A local server with a page at /ok, a redirect from /moved to /ok and nothing else
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let base = format!("http://{}", listener.local_addr().unwrap());
+/// std::thread::spawn(move || {
+///   for stream in listener.incoming() {
+///     let mut stream = stream.unwrap();
+///     let mut request = [0; 1024];
+///     let read = stream.read(&mut request).unwrap();
+///     let request = String::from_utf8_lossy(&request[..read]);
+///     let status = match request.split_whitespace().nth(1) {
+///       Some("/ok") => "200 OK",
+///       Some("/moved") => "301 Moved Permanently\r\nLocation: /ok",
+///       _ => "404 Not Found",
+///     };
+///     let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+///     stream.write_all(response.as_bytes()).unwrap();
+///   }
+/// });
+///
+/// let urls = vec![format!("{}/ok", base), format!("{}/moved", base), format!("{}/gone", base)];
+/// let checks = check_urls(&urls, &CheckOptions::default());
+/// assert_eq!(checks[0].status, Some(200));
+/// assert!(!checks[0].is_broken() && !checks[0].is_redirected());
+/// assert!(checks[1].is_redirected());
+/// assert_eq!(checks[1].redirect, Some(format!("{}/ok", base)));
+/// assert_eq!(checks[2].status, Some(404));
+/// assert!(checks[2].is_broken());
+/// ```
+pub fn check_urls(urls: &[String], options: &CheckOptions) -> Vec<UrlCheck> {
+    let agent = check_agent(options);
+    let queue = Arc::new(Mutex::new(urls.iter().cloned().enumerate().rev().collect::<Vec<(usize, String)>>()));
+    let (sender, receiver) = mpsc::channel();
+    for _ in 0..options.concurrency.max(1).min(urls.len()) {
+        let (queue, sender, agent, retries) = (queue.clone(), sender.clone(), agent.clone(), options.retries);
+        thread::spawn(move || loop {
+            let next = queue.lock().expect("The URL queue was poisoned").pop();
+            match next {
+                Some((position, url)) => {
+                    if sender.send((position, check_url(&agent, &url, retries))).is_err() {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        });
+    }
+    drop(sender);
+
+    let mut checks: Vec<(usize, UrlCheck)> = receiver.iter().collect();
+    checks.sort_by_key(|(position, _)| *position);
+    checks.into_iter().map(|(_, check)| check).collect()
+}
+
+/// Save checks, replacing the last check of the same URLs.
+pub fn save_url_checks(checks: &[UrlCheck]) -> QueryResult<usize> {
+    let connection = shared_connection();
+
+    connection.transaction(|| {
+        for check in checks {
+            diesel::insert_into(url_checks::table)
+                .values(check)
+                .on_conflict(url_checks::url)
+                .do_update()
+                .set(check)
+                .execute(&*connection)?;
+        }
+        Ok(checks.len())
+    })
+}
+
+// The same URL, give or take the trailing slash added to a bare host
+fn same_url(first: &str, second: &str) -> bool {
+    first.trim_end_matches('/') == second.trim_end_matches('/')
+}
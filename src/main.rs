@@ -16,7 +16,61 @@ extern crate dialoguer;
 
 // use tempfile::NamedTempFile;
 use std::fs;
+use std::path::Path;
 use nautilus::*;
+use nautilus::markdown::{
+    parse_markdown,
+    render_markdown,
+    render_links_data,
+    markdown_filename,
+    find_markdown_files,
+    slug_from_path,
+    date_from_path,
+};
+use nautilus::wxr::{parse_wxr, parse_blogroll, WxrSkipped};
+use nautilus::bookmarks::{parse_bookmarks, render_bookmarks, save_bookmarks, skip_existing_links};
+use nautilus::systemdata::{
+    check_system_data,
+    delete_system_schema,
+    get_system_path,
+    parse_value,
+    read_system_schema,
+    save_system_schema,
+    set_system_path,
+};
+use nautilus::menus::{
+    add_menu_item,
+    create_menu,
+    delete_menu,
+    edit_menu_item,
+    move_menu_item,
+    read_menu_item,
+    read_menu_items,
+    read_menu_tree,
+    read_menus,
+    remove_menu_item,
+    MenuTarget,
+    MenuTreeItem,
+};
+use nautilus::import::{
+    import_posts,
+    import_links,
+    import_posts_and_links,
+    import_system,
+    OnConflict,
+    PostRecord,
+    LinkRecord,
+    SystemRecord,
+};
+use nautilus::validate::{import_schema, validate_import, ImportFile};
+use nautilus::output::{
+    render_list,
+    render_one,
+    parse_fields,
+    all_fields,
+    list_fields,
+    OutputFormat,
+};
 use std::{thread, time};
 use std::io::prelude::*;
 use self::models::{
@@ -31,17 +85,53 @@ use self::models::{
     Content::PostContent
 };
 use prettytable::{Table};
+use diesel::Connection;
+use diesel::pg::PgConnection;
+use clap::{load_yaml, App, ArgMatches};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use serde_json::json;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 use simple_prompts::{edit_prompt, prompt};
-use vim_edit::{vim_create, vim_edit};
+use nautilus::editor::{body_extension, edit_text};
+use nautilus::config::{
+    active_profile,
+    active_profile_name,
+    config_file,
+    hide_password,
+    read_config,
+    select_profile,
+    selected_profile,
+    write_config,
+    Profile,
+    PROFILE_SETTINGS,
+};
+use nautilus::migrations::{
+    create_database,
+    migration_status,
+    revert_latest_migration,
+    run_pending_migrations,
+};
+use nautilus::doctor::run_checks;
+use nautilus::bulk::{apply_delete, apply_retag, parse_id_ranges, plan_retag, select_content, BulkItem, Retag, Selection};
+use nautilus::pageinfo::{fetch_page_info, PageInfo};
+use nautilus::linkcheck::{check_urls, content_urls, save_url_checks, CheckOptions};
+use nautilus::tags::{normalize_tag, normalize_tags, plan_normalize, plan_tag_change, tag_counts};
+use nautilus::drafts::{draft_file, find_draft, list_drafts, remove_draft, save_draft, submit_draft, Draft, DraftRecord};
 use dialoguer::{theme::ColorfulTheme, Select};
 // Testing
-use chrono::{NaiveDate, NaiveDateTime};
+use chrono::NaiveDate;
 use chrono::format::ParseError;
 
-// @TODO Need the import function to handle insert new as well as update existing
-// @TODO Navigation content type (maybe just use links tagged nav????)
-
 /// The command line CMS
 ///
 /// Two basic content types supported in the library and app with the following fields
@@ -62,6 +152,7 @@ use chrono::format::ParseError;
 ///   - Tags: String
 ///   - Time: Datetime+TZ, Auto
 ///
+#[derive(Clone, Copy)]
 struct State {
     verbose: bool,
     debug: bool,
@@ -74,37 +165,82 @@ impl State {
             debug: false,
         }
     }
+
+    fn from_args(matches: &ArgMatches) -> State {
+        let mut state = State::new();
+
+        // This will work for arg flags
+        if matches.is_present("verbose") {
+            state.verbose = true;
+        }
+        if matches.is_present("debug") {
+            state.debug = true;
+        }
+        state
+    }
 }
 
+// Set when the REPL is running so failed commands return to the prompt instead of exiting
+static IN_REPL: AtomicBool = AtomicBool::new(false);
+
+// Set for a REPL command run with --debug so its errors say where they came from
+static REPL_DEBUG: AtomicBool = AtomicBool::new(false);
+
+// The payload of a command failure unwinding back to the REPL
+struct CommandFailed;
+
 // Setup initial state and parse args to modify state or trigger functions
 fn main() {
-    let mut state = State::new();
-    use clap::{load_yaml, App};
-
     let yaml = load_yaml!("cli.yml");
 
     let matches = App::from(yaml).get_matches();
+    let state = State::from_args(&matches);
+    select_profile(matches.value_of("site"));
 
-    // This will work for arg flags
-    if matches.is_present("verbose") {
-        state.verbose = true;
+    match matches.subcommand() {
+        ("", None) => repl(state),
+        _ => run_command(state, &matches),
     }
-    if matches.is_present("debug") {
-        state.debug = true;
+}
+
+// Run a parsed command line, from the shell or from a REPL line
+fn run_command(state: State, matches: &ArgMatches) {
+    if let Some(site) = matches.value_of("site") {
+        select_profile(Some(site));
     }
 
     match matches.subcommand() {
         ("create", Some(_clone_matches)) => {
-            create_content(state)
+            match _clone_matches.value_of("content_type") {
+                Some(content_type) => create_from_args(state, content_type, _clone_matches),
+                None => create_content(state),
+            }
+        }
+        ("list", Some(_clone_matches)) => {
+            let content_type = _clone_matches.value_of("content_type").unwrap();
+            let (format, fields) = output_args(content_type, _clone_matches, list_fields(content_type));
+            list_content(content_type, format, &fields)
         }
         ("read", Some(_clone_matches)) => {
-            read_content()
+            match _clone_matches.value_of("content_type") {
+                Some(content_type) => read_from_args(content_type, _clone_matches),
+                None => read_content(),
+            }
         }
         ("edit", Some(_clone_matches)) => {
-            edit_content(state)
+            match _clone_matches.value_of("content_type") {
+                Some(content_type) => edit_from_args(state, content_type, _clone_matches),
+                None => edit_content(state),
+            }
         }
         ("delete", Some(_clone_matches)) => {
-            delete_content()
+            match _clone_matches.value_of("content_type") {
+                Some(content_type) => delete_from_args(content_type, _clone_matches),
+                None => delete_content(),
+            }
+        }
+        ("recover", Some(_clone_matches)) => {
+            recover(state, _clone_matches.value_of("draft"), _clone_matches.is_present("discard"));
         }
         ("testing", Some(_clone_matches)) => {
             let this_post = Post {
@@ -116,7 +252,9 @@ fn main() {
                 summary: String::from("Some summary"),
                 version: 1,
                 updated: NaiveDate::from_ymd(2016, 7, 8).and_hms(9, 10, 11),
-                parent: 1,
+                parent: Some(1),
+                slug: String::from("some-title"),
+                trashed: None,
             };
             let node = _create_node();
             let content = Content::PostContent(this_post);
@@ -130,29 +268,444 @@ fn main() {
             //     time: NaiveDate::from_ymd(2016, 7, 8).and_hms(9, 10, 11),
             //     version: 1,
             //     updated: NaiveDate::from_ymd(2016, 7, 8).and_hms(9, 10, 11),
-            //     parent: 1,
+            //     parent: Some(1),
             // };
             // let node = _create_node();
             // let other_content = Content::LinkContent(this_link);
             // _save_node_content(node, other_content);
         }
         ("export", Some(_clone_matches)) => {
-            let this_post = _clone_matches.value_of("post_id")
-                .unwrap()
-                .parse::<i32>()
-                .unwrap_or(0);
+            let content_type = _clone_matches.value_of("content_type").unwrap();
+            let content_ids = _clone_matches.value_of("content_id").unwrap();
             let export_filename = _clone_matches.value_of("export_filename").unwrap();
-            export_post(state, this_post, export_filename)
+            export_content(state, content_type, content_ids, export_filename)
         }
         ("import", Some(_clone_matches)) => {
+            let content_type = _clone_matches.value_of("content_type").unwrap();
+            let import_filename = _clone_matches.value_of("import_filename").unwrap();
+            let on_conflict = _clone_matches.value_of("on-conflict")
+                .unwrap()
+                .parse::<OnConflict>()
+                .unwrap();
+            let dry_run = _clone_matches.is_present("dry-run");
+            import_content(state, content_type, import_filename, on_conflict, dry_run)
+        }
+        ("import-markdown", Some(_clone_matches)) => {
+            let import_path = _clone_matches.value_of("import_path").unwrap();
+            import_markdown(
+                state,
+                import_path,
+                _clone_matches.is_present("drafts"),
+                _clone_matches.is_present("dry-run"),
+            )
+        }
+        ("export-markdown", Some(_clone_matches)) => {
+            let out_dir = _clone_matches.value_of("out").unwrap();
+            export_markdown(state, out_dir, _clone_matches.value_of("links"), _clone_matches.is_present("hugo"))
+        }
+        ("import-wxr", Some(_clone_matches)) => {
             let import_filename = _clone_matches.value_of("import_filename").unwrap();
-            import_post(state, import_filename)
+            import_wxr(
+                state,
+                import_filename,
+                _clone_matches.value_of("blogroll"),
+                _clone_matches.is_present("drafts"),
+                _clone_matches.is_present("dry-run"),
+            )
         }
-        ("", None) => println!("No subcommand used"), // @TODO add a default REPL action here
+        ("import-bookmarks", Some(_clone_matches)) => {
+            let import_filename = _clone_matches.value_of("import_filename").unwrap();
+            import_bookmarks(state, import_filename, _clone_matches.is_present("dry-run"))
+        }
+        ("export-bookmarks", Some(_clone_matches)) => {
+            export_bookmarks(_clone_matches.value_of("export_filename"))
+        }
+        ("schema", Some(_clone_matches)) => {
+            match _clone_matches.subcommand() {
+                ("export", Some(_export_matches)) => {
+                    let content_type = _export_matches.value_of("content_type").unwrap();
+                    let schema = import_schema(content_type);
+                    println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+                }
+                _ => println!("Use \"schema export <content_type>\" to print a JSON Schema"),
+            }
+        }
+        ("config", Some(_clone_matches)) => {
+            match _clone_matches.subcommand() {
+                ("show", Some(_)) => config_show(),
+                ("set", Some(_set_matches)) => config_set(
+                    _set_matches.value_of("setting").unwrap(),
+                    _set_matches.value_of("value").unwrap(),
+                ),
+                _ => println!("Use \"config show\" or \"config set <setting> <value>\""),
+            }
+        }
+        ("db", Some(_clone_matches)) => {
+            match _clone_matches.subcommand() {
+                ("init", Some(_)) => db_init(state),
+                ("migrate", Some(_)) => db_migrate(state),
+                ("status", Some(_)) => db_status(),
+                ("rollback", Some(_rollback_matches)) => db_rollback(_rollback_matches.is_present("yes")),
+                _ => println!("Use \"db init\", \"db migrate\", \"db status\" or \"db rollback\""),
+            }
+        }
+        ("doctor", Some(_clone_matches)) => doctor(state, _clone_matches.is_present("fix")),
+        ("trash", Some(_clone_matches)) => {
+            match _clone_matches.subcommand() {
+                ("list", Some(_list_matches)) => trash_list(
+                    _list_matches.value_of("content_type"),
+                    list_format(_list_matches),
+                ),
+                ("restore", Some(_restore_matches)) => trash_restore(
+                    _restore_matches.value_of("content_type").unwrap(),
+                    _restore_matches.value_of("content_id").unwrap(),
+                ),
+                ("purge", Some(_purge_matches)) => trash_purge(
+                    state,
+                    _purge_matches.value_of("older-than").unwrap(),
+                    _purge_matches.is_present("yes"),
+                ),
+                _ => println!("Use \"trash list\", \"trash restore <content_type> <id>\" or \"trash purge\""),
+            }
+        }
+        ("bulk", Some(_clone_matches)) => {
+            match _clone_matches.subcommand() {
+                ("retag", Some(_retag_matches)) => bulk_retag(state, _retag_matches),
+                ("delete", Some(_delete_matches)) => bulk_delete(state, _delete_matches),
+                _ => println!("Use \"bulk retag\" or \"bulk delete\""),
+            }
+        }
+        ("tags", Some(_clone_matches)) => {
+            match _clone_matches.subcommand() {
+                ("rename", Some(_rename_matches)) => tags_change(
+                    state,
+                    &[_rename_matches.value_of("tag").unwrap()],
+                    _rename_matches.value_of("new_tag"),
+                    _rename_matches.is_present("yes"),
+                ),
+                ("merge", Some(_merge_matches)) => tags_change(
+                    state,
+                    &_merge_matches.values_of("tags").unwrap().collect::<Vec<&str>>(),
+                    _merge_matches.value_of("into"),
+                    _merge_matches.is_present("yes"),
+                ),
+                ("delete", Some(_delete_matches)) => tags_change(
+                    state,
+                    &[_delete_matches.value_of("tag").unwrap()],
+                    None,
+                    _delete_matches.is_present("yes"),
+                ),
+                ("normalize", Some(_normalize_matches)) => confirm_retag(
+                    state,
+                    plan_normalize().unwrap_or_else(|e| fail(&e.to_string())),
+                    _normalize_matches.is_present("yes"),
+                ),
+                ("list", Some(_list_matches)) => tags_list(list_format(_list_matches)),
+                _ => tags_list(OutputFormat::Table),
+            }
+        }
+        ("check-links", Some(_clone_matches)) => {
+            let options = CheckOptions {
+                concurrency: parse_count(_clone_matches.value_of("concurrency").unwrap()) as usize,
+                timeout: time::Duration::from_secs(u64::from(parse_count(_clone_matches.value_of("timeout").unwrap()))),
+                retries: parse_count(_clone_matches.value_of("retries").unwrap()),
+            };
+            check_links(state, &options)
+        }
+        ("system", Some(_clone_matches)) => {
+            match _clone_matches.subcommand() {
+                ("list", Some(_list_matches)) => system_list(_list_matches),
+                ("get", Some(_get_matches)) => system_get(_get_matches.value_of("path").unwrap()),
+                ("set", Some(_set_matches)) => system_set(
+                    state,
+                    _set_matches.value_of("path").unwrap(),
+                    _set_matches.value_of("value").unwrap(),
+                    _set_matches.is_present("string"),
+                ),
+                ("rename", Some(_rename_matches)) => system_rename(
+                    _rename_matches.value_of("key").unwrap(),
+                    _rename_matches.value_of("new_key").unwrap(),
+                ),
+                ("schema", Some(_schema_matches)) => system_schema(
+                    _schema_matches.value_of("key").unwrap(),
+                    _schema_matches.value_of("schema_file"),
+                    _schema_matches.is_present("clear"),
+                ),
+                _ => println!("Use system list, get, set, rename or schema, see nautilus system --help"),
+            }
+        }
+        ("menu", Some(_clone_matches)) => {
+            match _clone_matches.subcommand() {
+                ("create", Some(_create_matches)) => menu_create(
+                    state,
+                    _create_matches.value_of("name").unwrap(),
+                    _create_matches.value_of("title").unwrap_or(""),
+                ),
+                ("show", Some(_show_matches)) => menu_show(
+                    _show_matches.value_of("name").unwrap(),
+                    _show_matches.value_of("format").unwrap(),
+                ),
+                ("add", Some(_add_matches)) => menu_add(state, _add_matches),
+                ("edit", Some(_edit_matches)) => menu_edit(state, _edit_matches),
+                ("move", Some(_move_matches)) => menu_move(state, _move_matches),
+                ("remove", Some(_remove_matches)) => menu_remove(
+                    parse_id(_remove_matches.value_of("item_id").unwrap()),
+                    _remove_matches.is_present("yes"),
+                ),
+                ("delete", Some(_delete_matches)) => menu_delete(
+                    _delete_matches.value_of("name").unwrap(),
+                    _delete_matches.is_present("yes"),
+                ),
+                ("list", Some(_list_matches)) => menu_list(list_format(_list_matches)),
+                _ => menu_list(OutputFormat::Table),
+            }
+        }
+        ("", None) => println!("No subcommand used"),
         _ => unreachable!(),
     }
 }
 
+// The interactive mode, every subcommand can be run at the prompt with a shared database connection
+fn repl(state: State) {
+    let yaml = load_yaml!("cli.yml");
+    let commands: Vec<String> = yaml["subcommands"].as_vec()
+        .map(|subcommands| subcommands.iter()
+            .filter_map(|subcommand| subcommand.as_hash())
+            .flat_map(|subcommand| subcommand.keys())
+            .filter_map(|name| name.as_str())
+            .map(String::from)
+            .collect())
+        .unwrap_or_default();
+
+    IN_REPL.store(true, Ordering::Relaxed);
+    let startup_site = selected_profile();
+    panic::set_hook(Box::new(|info| {
+        match info.payload().downcast_ref::<String>() {
+            Some(message) => println!("Error: {}", message),
+            None => match info.payload().downcast_ref::<&str>() {
+                Some(message) => println!("Error: {}", message),
+                None => println!("Error: {}", info),
+            },
+        }
+        if let (true, Some(location)) = (REPL_DEBUG.load(Ordering::Relaxed), info.location()) {
+            println!("  at {}", location);
+        }
+    }));
+
+    let mut editor = Editor::<ReplHelper>::new();
+    editor.set_helper(Some(ReplHelper {
+        commands,
+        completions: RefCell::new(ReplCompletions::default()),
+    }));
+    let history_file = repl_history_file();
+    if let Some(history_file) = &history_file {
+        let _ = editor.load_history(history_file);
+    }
+    println!("Nautilus {}, \"help\" for commands, \"exit\" or Ctrl-D to leave", env!("CARGO_PKG_VERSION"));
+
+    if let Some(helper) = editor.helper() {
+        helper.refresh();
+    }
+    loop {
+        let line = match editor.readline("n4> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                println!("Error: {}", e);
+                break;
+            }
+        };
+        let words = split_command_line(&line);
+        if words.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str());
+        if words[0] == "exit" || words[0] == "quit" {
+            break;
+        }
+
+        let mut args = vec![String::from("nautilus")];
+        if words[0] == "help" {
+            args.push(String::from("--help"));
+        }
+        args.extend(words);
+        let matches = match App::from(yaml).try_get_matches_from(args) {
+            Ok(matches) => matches,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            }
+        };
+        if let ("", None) = matches.subcommand() {
+            continue;
+        }
+
+        let line_state = State::from_args(&matches);
+        let command_state = State {
+            verbose: state.verbose || line_state.verbose,
+            debug: state.debug || line_state.debug,
+        };
+        REPL_DEBUG.store(command_state.debug, Ordering::Relaxed);
+        // Failed commands unwind back here, the error has already been printed.  The connection
+        // may be broken or left inside a transaction, the next command connects again.
+        if panic::catch_unwind(AssertUnwindSafe(|| run_command(command_state, &matches))).is_err() {
+            reset_shared_connection();
+        }
+        // A --site on the line is for that command only
+        select_profile(startup_site.as_deref());
+        if let Some(helper) = editor.helper() {
+            helper.refresh();
+        }
+    }
+
+    if let Some(history_file) = &history_file {
+        if let Err(e) = editor.save_history(history_file) {
+            println!("Could not save the REPL history: {}", e);
+        }
+    }
+}
+
+// The REPL history lives with the rest of the config in ~/.config/N4/
+fn repl_history_file() -> Option<PathBuf> {
+    let config_dir = dirs::config_dir()?.join("N4");
+    fs::create_dir_all(&config_dir).ok()?;
+    Some(config_dir.join("history.txt"))
+}
+
+// Split a REPL line into args like a shell would, quotes group words and backslash escapes
+fn split_command_line(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    let mut characters = line.chars();
+    while let Some(character) = characters.next() {
+        match (quote, character) {
+            (Some(open), character) if character == open => quote = None,
+            (None, '"') | (None, '\'') => {
+                quote = Some(character);
+                in_word = true;
+            }
+            (Some('\''), character) => word.push(character),
+            (_, '\\') => {
+                if let Some(escaped) = characters.next() {
+                    word.push(escaped);
+                }
+                in_word = true;
+            }
+            (None, character) if character.is_whitespace() => {
+                if in_word {
+                    words.push(word.clone());
+                    word.clear();
+                    in_word = false;
+                }
+            }
+            (_, character) => {
+                word.push(character);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+// Tab completion for the REPL, commands first, then content types, then ids or system keys
+struct ReplHelper {
+    commands: Vec<String>,
+    completions: RefCell<ReplCompletions>,
+}
+
+#[derive(Default)]
+struct ReplCompletions {
+    post_ids: Vec<String>,
+    link_ids: Vec<String>,
+    system_keys: Vec<String>,
+    menu_names: Vec<String>,
+}
+
+impl ReplHelper {
+    // Reload the ids and keys after every command, they may have changed
+    fn refresh(&self) {
+        let completions = panic::catch_unwind(|| ReplCompletions {
+            post_ids: read_all_posts().iter().map(|post| post.id.to_string()).collect(),
+            link_ids: read_all_links().iter().map(|link| link.id.to_string()).collect(),
+            system_keys: read_all_system().into_iter().map(|system| system.key).collect(),
+            menu_names: read_menus().unwrap_or_default().into_iter().map(|menu| menu.name).collect(),
+        });
+        match completions {
+            Ok(completions) => {
+                self.completions.replace(completions);
+            }
+            Err(_) => reset_shared_connection(),
+        }
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map(|space| space + 1).unwrap_or(0);
+        let partial = &line[start..];
+        let words = split_command_line(&line[..start]);
+
+        let completions = self.completions.borrow();
+        let candidates: Vec<String> = match words.len() {
+            0 => self.commands.clone(),
+            1 if CONTENT_COMMANDS.contains(&words[0].as_str()) => {
+                vec![String::from("post"), String::from("link"), String::from("system")]
+            }
+            2 if ID_COMMANDS.contains(&words[0].as_str()) => match words[1].as_str() {
+                "post" => completions.post_ids.clone(),
+                "link" => completions.link_ids.clone(),
+                "system" => completions.system_keys.clone(),
+                _ => Vec::new(),
+            },
+            1 if words[0] == "system" => ["list", "get", "set", "rename", "schema"].iter().map(|command| String::from(*command)).collect(),
+            2 if words[0] == "system" => completions.system_keys.clone(),
+            1 if words[0] == "menu" => ["create", "show", "add", "edit", "move", "remove", "delete"].iter().map(|command| String::from(*command)).collect(),
+            2 if words[0] == "menu" && ["show", "add", "delete"].contains(&words[1].as_str()) => completions.menu_names.clone(),
+            _ => Vec::new(),
+        };
+
+        let pairs = candidates.into_iter()
+            .filter(|candidate| candidate.starts_with(partial))
+            .map(|candidate| Pair {
+                display: candidate.clone(),
+                replacement: format!("{} ", candidate),
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+// The commands that take a content type, and the ones that follow it with an id or key
+const CONTENT_COMMANDS: &[&str] = &["create", "read", "edit", "delete", "list", "export", "import"];
+const ID_COMMANDS: &[&str] = &["read", "edit", "delete", "export"];
+
+impl Hinter for ReplHelper {}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+// Stop a command with an error message.  From the shell this exits with an error status, in the
+// REPL it unwinds back to the prompt.
+fn fail(message: &str) -> ! {
+    println!("{}", message);
+    if IN_REPL.load(Ordering::Relaxed) {
+        panic::resume_unwind(Box::new(CommandFailed));
+    }
+    std::process::exit(1);
+}
+
 // Interactive Functions
 fn create_content(state: State) {
     let verbose = state.verbose; // Implicit copy
@@ -302,8 +855,7 @@ fn delete_content() {
         "Article" => {
             let post_id = select_article();
             if post_id != 0 {
-                prompt("Are you sure?");
-                delete_post(post_id)
+                confirm_delete("post", &post_id.to_string())
             }
             else {
                 println!("Nothing entered, nothing to delete.")
@@ -312,8 +864,7 @@ fn delete_content() {
         "Link" => {
             let link_id = select_link();
             if link_id != 0 {
-                prompt("Are you sure?");
-                delete_link(link_id)
+                confirm_delete("link", &link_id.to_string())
             }
             else {
                 println!("Nothing entered, nothing to delete.")
@@ -325,14 +876,292 @@ fn delete_content() {
                 println!("Nothing entered, nothing to delete.")
             }
             else {
-                prompt("Are you sure?");
-                delete_system(&key)
+                confirm_delete("system", &key)
             }
         }
         _ => println!("How did you manage no selection?  ERROR")
     }
 }
 
+// Non-interactive functions, everything comes from the command line args
+fn create_from_args(state: State, content_type: &str, args: &ArgMatches) {
+    match content_type {
+        "post" => {
+            let raw_title = required_arg(args, "title", "create post");
+            let raw_body = read_body(&required_arg(args, "body-file", "create post"));
+            let raw_tags = args.value_of("tags").map(normalize_tags).unwrap_or_else(default_tags);
+            let raw_summary = args.value_of("summary").unwrap_or("").to_string();
+            let raw_slug = args.value_of("slug")
+                .map(String::from)
+                .unwrap_or_else(|| slugify(&raw_title));
+
+            let rawpost = NewPost {
+                title: &raw_title,
+                body: &raw_body,
+                tags: &raw_tags,
+                summary: &raw_summary,
+                slug: &raw_slug,
+            };
+            let post = create_post(&rawpost);
+            println!("Saved post with id {}", &post.id);
+        }
+        "link" => {
+            let raw_url = required_arg(args, "url", "create link");
+            let (raw_text, raw_title, raw_url) = if args.is_present("fetch") {
+                fetched_link_fields(&raw_url, args)
+            } else {
+                let raw_text = required_arg(args, "text", "create link");
+                let raw_title = args.value_of("title").map(String::from).unwrap_or_else(|| raw_text.clone());
+                (raw_text, raw_title, raw_url)
+            };
+            let raw_tags = args.value_of("tags").map(normalize_tags).unwrap_or_else(default_tags);
+
+            let rawlink = NewLink {
+                text: &raw_text,
+                title: &raw_title,
+                url: &raw_url,
+                tags: &raw_tags,
+            };
+            let link = create_link(&rawlink);
+            println!("Saved link with id {}", &link.id);
+        }
+        "system" => {
+            let raw_key = required_arg(args, "key", "create system");
+            let raw_data = match args.value_of("body-file") {
+                Some(body_file) => parse_value(&read_body(body_file)),
+                None => parse_value(&required_arg(args, "data", "create system")),
+            };
+            check_system_data(&raw_key, &raw_data).unwrap_or_else(|e| fail(&e));
+
+            let rawsystem = NewSystem {
+                key: &raw_key,
+                data: &raw_data,
+            };
+            let new_system = create_system(&rawsystem).unwrap_or_else(|e| fail(&e));
+            println!("Saved system key {}", &new_system.key);
+        }
+        _ => unreachable!(),
+    }
+    if state.verbose {
+        println!("Created : {}", content_type);
+    }
+}
+
+// The link text, title and URL for create link --fetch, the text and title given as args are kept
+// and the rest come from the page, confirmed unless --yes.  The URL is the canonical one of the page
+// or where it was redirected to, like in the interactive flow.
+fn fetched_link_fields(url: &str, args: &ArgMatches) -> (String, String, String) {
+    let (page_url, info) = match fetch_page_info(url) {
+        Ok((page_url, info)) => {
+            if let Some(canonical_url) = info.canonical_url.as_deref().filter(|canonical_url| *canonical_url != url) {
+                println!("The page gives {} as its canonical URL", canonical_url);
+            } else if page_url != url {
+                println!("The page is at {} now", page_url);
+            }
+            (page_url, info)
+        }
+        Err(e) => {
+            println!("Warning, nothing was prefilled: {}", e);
+            (String::from(url), PageInfo::default())
+        }
+    };
+    let field = |name: &str, prefill: Option<&str>, question: &str| match args.value_of(name) {
+        Some(value) => String::from(value),
+        None if args.is_present("yes") => String::from(prefill.unwrap_or("")),
+        None => edit_prompt(question, prefill.unwrap_or("")),
+    };
+    let raw_text = field("text", info.display_text(), "Display text: ");
+    let raw_title = field("title", info.hover_title(), "Hover title: ");
+    let prefilled_url = info.canonical_url.as_deref().unwrap_or(&page_url);
+    let raw_url = if args.is_present("yes") {
+        String::from(prefilled_url)
+    } else {
+        edit_prompt("Link URL: ", prefilled_url)
+    };
+    if raw_text.trim().is_empty() {
+        fail("create link needs --text, the page has no title to use");
+    }
+    (raw_text, raw_title, raw_url)
+}
+
+fn read_from_args(content_type: &str, args: &ArgMatches) {
+    let content_id = String::from(args.value_of("content_id").unwrap());
+    let (format, fields) = output_args(content_type, args, all_fields(content_type));
+    let output = match content_type {
+        "post" => render_one(&read_post(parse_id(&content_id)), &fields, format),
+        "link" => render_one(&read_link(parse_id(&content_id)), &fields, format),
+        "system" => render_one(&read_system(content_id), &fields, format),
+        _ => unreachable!(),
+    };
+    print!("{}", output);
+}
+
+// The --format and --fields args of list and read, the default fields are used without --fields
+fn output_args(content_type: &str, args: &ArgMatches, default_fields: Vec<&str>) -> (OutputFormat, Vec<String>) {
+    let format = args.value_of("format").unwrap_or("table").parse::<OutputFormat>().unwrap();
+    let fields = match args.value_of("fields") {
+        Some(fields) => parse_fields(content_type, fields).unwrap_or_else(|e| {
+            fail(&e.to_string());
+        }),
+        None => default_fields.into_iter().map(String::from).collect(),
+    };
+    (format, fields)
+}
+
+// The --format arg of the lists without --fields, like tags list
+fn list_format(args: &ArgMatches) -> OutputFormat {
+    args.value_of("format").unwrap_or("table").parse::<OutputFormat>().unwrap()
+}
+
+fn field_names(fields: &[&str]) -> Vec<String> {
+    fields.iter().map(|field| String::from(*field)).collect()
+}
+
+fn edit_from_args(state: State, content_type: &str, args: &ArgMatches) {
+    let content_id = String::from(args.value_of("content_id").unwrap());
+    let result = match content_type {
+        "post" => {
+            let current_content: Post = read_post(parse_id(&content_id));
+            let edited_content = Post {
+                title: args.value_of("title").map(String::from).unwrap_or(current_content.title.clone()),
+                body: args.value_of("body-file").map(read_body).unwrap_or(current_content.body.clone()),
+                tags: args.value_of("tags").map(normalize_tags).unwrap_or(current_content.tags.clone()),
+                summary: args.value_of("summary").map(String::from).unwrap_or(current_content.summary.clone()),
+                slug: args.value_of("slug").map(String::from).unwrap_or(current_content.slug.clone()),
+                ..current_content
+            };
+            update_post(&edited_content)
+        }
+        "link" => {
+            let current_content: Link = read_link(parse_id(&content_id));
+            let edited_content = Link {
+                text: args.value_of("text").map(String::from).unwrap_or(current_content.text.clone()),
+                title: args.value_of("title").map(String::from).unwrap_or(current_content.title.clone()),
+                url: args.value_of("url").map(String::from).unwrap_or(current_content.url.clone()),
+                tags: args.value_of("tags").map(normalize_tags).unwrap_or(current_content.tags.clone()),
+                ..current_content
+            };
+            update_link(&edited_content)
+        }
+        "system" => {
+            let current_content: System = read_system(content_id.clone());
+            let raw_data = match (args.value_of("body-file"), args.value_of("data")) {
+                (Some(body_file), _) => parse_value(&read_body(body_file)),
+                (None, Some(data)) => parse_value(data),
+                (None, None) => current_content.data.clone(),
+            };
+            check_system_data(&current_content.key, &raw_data).unwrap_or_else(|e| fail(&e));
+            let edited_content = System {
+                data: raw_data,
+                ..current_content
+            };
+            update_system(&edited_content)
+        }
+        _ => unreachable!(),
+    };
+
+    match result {
+        Ok(0) => fail(&format!("Could not update {} {}, it was deleted or saved again meanwhile", &content_type, &content_id)),
+        Ok(updated) => {
+            if state.verbose {
+                println!("Update {} result: {:?}", &content_type, &updated);
+            }
+        }
+        Err(e) => {
+            fail(&format!("Could not update {} {}: {}", &content_type, &content_id, e));
+        }
+    }
+    if let ("system", Some(new_key)) = (content_type, args.value_of("key")) {
+        system_rename(&content_id, new_key);
+    }
+}
+
+fn delete_from_args(content_type: &str, args: &ArgMatches) {
+    let content_id = String::from(args.value_of("content_id").unwrap());
+    if args.is_present("yes") {
+        trash_content(content_type, &content_id);
+    } else {
+        confirm_delete(content_type, &content_id);
+    }
+}
+
+// Ask before deleting, anything but yes keeps the content
+fn confirm_delete(content_type: &str, content_id: &str) {
+    if confirm(&format!("Delete {} {}? [y/N] ", &content_type, &content_id)) {
+        trash_content(content_type, content_id);
+    } else {
+        println!("Nothing deleted.");
+    }
+}
+
+// Deleted content goes to the trash, where it can be restored until it's purged
+fn trash_content(content_type: &str, content_id: &str) {
+    let trashed = match content_type {
+        "post" => delete_post(parse_id(content_id)),
+        "link" => delete_link(parse_id(content_id)),
+        "system" => delete_system(content_id),
+        _ => unreachable!(),
+    };
+    if trashed == 0 {
+        fail(&format!("No {} {} to delete", content_type, content_id));
+    }
+    println!(
+        "Moved {} {} to the trash, restore it with: nautilus trash restore {} {}",
+        content_type, content_id, content_type, content_id
+    );
+}
+
+// An arg the non-interactive command can't do without, exits with a usage message when missing
+fn required_arg(args: &ArgMatches, name: &str, command: &str) -> String {
+    match args.value_of(name) {
+        Some(value) => String::from(value),
+        None => {
+            fail(&format!("{} needs --{} when run with arguments", &command, &name));
+        }
+    }
+}
+
+// The site profile in use, empty settings without one
+fn site_profile() -> Profile {
+    active_profile().unwrap_or_else(|e| fail(&e))
+        .map(|(_, profile)| profile)
+        .unwrap_or_default()
+}
+
+// The default tags of the site profile for new content, none without a profile
+fn default_tags() -> String {
+    site_profile().default_tags.unwrap_or_default()
+}
+
+// Links with relative URLs made absolute with the base URL of the site profile
+fn absolute_links(links: Vec<Link>, profile: &Profile) -> Vec<Link> {
+    links.into_iter()
+        .map(|link| Link { url: profile.absolute_url(&link.url), ..link })
+        .collect()
+}
+
+// Read a post body (or system data) from a file, "-" reads it from stdin
+fn read_body(body_file: &str) -> String {
+    let mut body = String::new();
+    if body_file == "-" {
+        std::io::stdin().read_to_string(&mut body)
+            .expect("Could not read the body from stdin");
+    } else {
+        body = fs::read_to_string(body_file)
+            .expect("Could not open the body file");
+    }
+    body
+}
+
+fn parse_id(content_id: &str) -> i32 {
+    match content_id.parse::<i32>() {
+        Ok(id) => id,
+        Err(_) => {
+            fail(&format!("{} is not an id number", &content_id));
+        }
+    }
+}
+
 fn select_article() -> i32 {
     list_posts();
     prompt("Enter an ID number to edit: ")
@@ -353,26 +1182,24 @@ fn select_system() -> String {
 }
 
 // <-- Primary functions -->
-fn list_posts() {
-    let all_posts: Vec<Post> = read_all_posts();
+fn list_content(content_type: &str, format: OutputFormat, fields: &[String]) {
+    let output = match content_type {
+        "post" => render_list(&read_all_posts(), fields, format),
+        "link" => render_list(&read_all_links(), fields, format),
+        "system" => render_list(&read_all_system(), fields, format),
+        _ => unreachable!(),
+    };
+    print!("{}", output);
+}
 
-    let mut table = Table::new();
-    table.add_row(row!["ID", "TITLE", "SUMMARY", "TAGS", "TIME"]);
-    for post in all_posts {
-        table.add_row(row![&post.id, &post.title, &post.summary, &post.tags, &post.time]);
-    }
-    table.printstd();
+fn list_posts() {
+    let fields: Vec<String> = list_fields("post").into_iter().map(String::from).collect();
+    list_content("post", OutputFormat::Table, &fields);
 }
 
 fn list_links() {
-    let all_links: Vec<Link> = read_all_links();
-
-    let mut table = Table::new();
-    table.add_row(row!["ID", "TEXT", "URL", "TITLE", "TIME"]);
-    for link in all_links {
-        table.add_row(row![&link.id, &link.text, &link.url, &link.title, &link.time]);
-    }
-    table.printstd();
+    let fields: Vec<String> = list_fields("link").into_iter().map(String::from).collect();
+    list_content("link", OutputFormat::Table, &fields);
 }
 
 fn write_post(state: State) {
@@ -386,18 +1213,40 @@ fn write_post(state: State) {
         let delay = time::Duration::from_millis(750);
         thread::sleep(delay);
     }
-    let raw_body = vim_create();
-    let raw_tags = prompt("Tags: ");
+    let raw_body = edit_text("", "md").unwrap_or_else(|e| fail(&e));
+    let mut draft = Draft::new(DraftRecord::Post(PostRecord {
+        id: None,
+        title: raw_title.clone(),
+        body: raw_body.clone(),
+        time: None,
+        tags: None,
+        summary: None,
+        version: None,
+        updated: None,
+        slug: None,
+    }));
+    autosave(&draft);
+    let raw_tags = normalize_tags(&edit_prompt("Tags: ", &default_tags()));
     let raw_summary = prompt("Summary: ");
 
+    let raw_slug = slugify(&raw_title);
+    if let DraftRecord::Post(record) = &mut draft.record {
+        record.tags = Some(raw_tags.clone());
+        record.summary = Some(raw_summary.clone());
+        record.slug = Some(raw_slug.clone());
+    }
+    autosave(&draft);
+
     let rawpost = NewPost {
         title: &raw_title,
         body: &raw_body,
         tags: &raw_tags,
         summary: &raw_summary,
+        slug: &raw_slug,
     };
 
     let post = create_post(&rawpost);
+    discard_draft(&draft);
     if state.verbose {
         println!("\nSaved {} with id {}", &rawpost.title, post.id);
     }
@@ -415,21 +1264,43 @@ fn edit_post(state: State, post_id: i32) {
     let mut raw_body: String = current_content.body.clone();
     let mut raw_tags: String = current_content.tags.clone();
     let mut raw_summary: String = current_content.summary.clone();
+    let mut raw_slug: String = current_content.slug.clone();
 
     raw_title = edit_prompt("Edit title: ", &raw_title);
-    raw_body = vim_edit(raw_body);
-    raw_tags = edit_prompt("Edit tags: ", &raw_tags);
+    raw_body = edit_text(&raw_body, body_extension(&raw_body)).unwrap_or_else(|e| fail(&e));
+    let mut draft = Draft::new(DraftRecord::Post(PostRecord {
+        id: Some(post_id),
+        title: raw_title.clone(),
+        body: raw_body.clone(),
+        time: Some(current_content.time),
+        tags: Some(raw_tags.clone()),
+        summary: Some(raw_summary.clone()),
+        version: Some(current_content.version),
+        updated: None,
+        slug: Some(raw_slug.clone()),
+    }));
+    autosave(&draft);
+    raw_tags = normalize_tags(&edit_prompt("Edit tags: ", &raw_tags));
     raw_summary = edit_prompt("Edit summary: ", &raw_summary);
+    raw_slug = edit_prompt("Edit slug: ", &raw_slug);
+    if let DraftRecord::Post(record) = &mut draft.record {
+        record.tags = Some(raw_tags.clone());
+        record.summary = Some(raw_summary.clone());
+        record.slug = Some(raw_slug.clone());
+    }
+    autosave(&draft);
 
     let edited_content = Post {
         title: raw_title,
         body: raw_body,
         tags: raw_tags,
         summary: raw_summary,
+        slug: raw_slug,
         ..current_content
     };
 
-    let result = update_post(&edited_content).unwrap();
+    let result = update_post(&edited_content);
+    settle_draft(&draft, &result, &format!("Post {}", post_id));
 
     if state.verbose {
         println!("Update post result: {:?}", &result);
@@ -437,85 +1308,671 @@ fn edit_post(state: State, post_id: i32) {
 
 }
 
-fn show_post(post_id: i32) {
-    let output: Post = read_post(post_id);
-    println!("{:#?}", output)
-}
+fn config_show() {
+    let config = read_config().unwrap_or_else(|e| fail(&e));
+    println!("Config file: {}", config_file().display());
+    let active = active_profile_name(&config);
+    match &active {
+        Some(name) if config.profiles.contains_key(name) => println!("Using profile: {}", name),
+        Some(name) => fail(&format!("No profile named {}", name)),
+        None => println!("Using profile: none, settings come from the environment and .env files"),
+    }
 
-fn show_link(link_id: i32) {
-    let output: Link = read_link(link_id);
-    println!("{:#?}", output)
+    let mut table = Table::new();
+    table.add_row(row!["PROFILE", "SETTING", "VALUE"]);
+    for (name, profile) in &config.profiles {
+        let marker = if Some(name) == active.as_ref() { format!("* {}", name) } else { name.clone() };
+        for setting in PROFILE_SETTINGS {
+            let value = match (*setting, profile.get(setting)) {
+                ("database_url", Some(database_url)) => hide_password(database_url),
+                (_, Some(value)) => value.clone(),
+                (_, None) => String::new(),
+            };
+            table.add_row(row![marker, setting, value]);
+        }
+    }
+    if config.profiles.is_empty() {
+        println!("No profiles yet, add one with: nautilus --site <PROFILE> config set database_url <URL>");
+    } else {
+        table.printstd();
+    }
 }
 
-fn show_system(key: String) {
-    let output: System = read_system(key);
-    println!("{:#?}", output)
+fn config_set(setting: &str, value: &str) {
+    let mut config = read_config().unwrap_or_else(|e| fail(&e));
+    if setting == "default" {
+        config.default = if value.is_empty() { None } else { Some(String::from(value)) };
+    } else {
+        let name = match active_profile_name(&config) {
+            Some(name) => name,
+            None => fail("No profile picked, use --site <PROFILE> to pick the one to change"),
+        };
+        let profile = config.profiles.entry(name.clone()).or_insert_with(Profile::default);
+        profile.set(setting, value).unwrap_or_else(|e| fail(&e));
+        // The first profile becomes the default
+        if config.default.is_none() && config.profiles.len() == 1 {
+            config.default = Some(name);
+        }
+    }
+    write_config(&config).unwrap_or_else(|e| fail(&e));
+    // A new database_url takes effect on the next query
+    reset_shared_connection();
+    println!("Saved {}", config_file().display());
 }
 
-fn write_link(state: State) {
-    if state.verbose {
-        println!("Writing Link");
+// The db commands connect without the schema check, they're how the schema gets fixed
+fn db_init(state: State) {
+    let database_url = database_url();
+    if create_database(&database_url).unwrap_or_else(|e| fail(&e)) {
+        println!("Created the database");
+    } else if state.verbose {
+        println!("The database already exists");
     }
-    let raw_text = prompt("Display text: ");
-    let raw_title = prompt("Hover title: ");
-    let raw_url = prompt("Link URL: ");
-    let raw_tags = prompt("Tags: ");
-
-    let rawlink = NewLink {
-        text: &raw_text,
-        title: &raw_title,
-        url: &raw_url,
-        tags: &raw_tags,
-    };
+    db_migrate(state);
+}
 
-    let link = create_link(&rawlink);
-    if state.verbose {
-        println!("\nSaved {} with id {}", &link.text, &link.id);
+fn db_migrate(state: State) {
+    let connection = establish_connection();
+    let applied = run_pending_migrations(&connection)
+        .unwrap_or_else(|e| fail(&format!("Migration failed, it was rolled back: {}", e)));
+    if applied.is_empty() {
+        println!("The database is up to date");
     }
-
+    for migration in applied {
+        println!("Applied {}", migration.name);
+        if state.verbose {
+            println!("{}", migration.up);
+        }
+    }
+    // Reconnect in case the shared connection was refused for the old schema
+    reset_shared_connection();
 }
 
-fn edit_link(state: State, link_id: i32) {
-    if state.verbose {
-        println!("Editing link {}", link_id);
+fn db_status() {
+    let connection = establish_connection();
+    let status = migration_status(&connection).unwrap_or_else(|e| fail(&e.to_string()));
+    let mut table = Table::new();
+    table.add_row(row!["VERSION", "MIGRATION", "APPLIED"]);
+    for migration in &status {
+        let applied = match migration.run_on {
+            Some(run_on) => run_on.to_string(),
+            None => String::from("pending"),
+        };
+        table.add_row(row![
+            migration.version,
+            migration.name.unwrap_or("(unknown, from a newer nautilus)"),
+            applied
+        ]);
+    }
+    table.printstd();
+    let pending = status.iter().filter(|migration| migration.run_on.is_none()).count();
+    if pending > 0 {
+        println!("{} migrations pending, run \"nautilus db migrate\"", pending);
     }
+}
 
-    let current_content: Link = read_link(link_id);
+fn db_rollback(yes: bool) {
+    if !yes && !confirm("Revert the latest migration?  Its tables and columns are dropped with their content. [y/N] ") {
+        println!("Nothing reverted.");
+        return;
+    }
+    let connection = establish_connection();
+    match revert_latest_migration(&connection).unwrap_or_else(|e| fail(&e)) {
+        Some(migration) => println!("Reverted {}", migration.name),
+        None => println!("No migrations to revert"),
+    }
+    reset_shared_connection();
+}
 
-    let mut raw_text: String = current_content.text.clone();
-    let mut raw_title: String = current_content.title.clone();
-    let mut raw_url: String = current_content.url.clone();
-    let mut raw_tags: String = current_content.tags.clone();
+// Checks from the outside in, each needs the one before it to pass
+fn doctor(state: State, fix: bool) {
+    let connection = match PgConnection::establish(&database_url()) {
+        Ok(connection) => {
+            println!("ok       database connection");
+            connection
+        }
+        Err(e) => fail(&format!("FAILED   database connection: {}", e)),
+    };
 
-    raw_text = edit_prompt("Edit text: ", &raw_text);
-    raw_title = edit_prompt("Edit title: ", &raw_title);
-    raw_url = edit_prompt("Edit url: ", &raw_url);
-    raw_tags = edit_prompt("Edit tags: ", &raw_tags);
+    let status = migration_status(&connection)
+        .unwrap_or_else(|e| fail(&format!("FAILED   migrations: {}", e)));
+    let pending = status.iter().filter(|migration| migration.run_on.is_none()).count();
+    let unknown = status.iter().filter(|migration| migration.name.is_none()).count();
+    if pending > 0 {
+        fail(&format!("FAILED   migrations: {} pending, run \"nautilus db migrate\" before checking the content", pending));
+    } else if unknown > 0 {
+        println!("warning  migrations: {} applied by a newer nautilus", unknown);
+    } else {
+        println!("ok       migrations");
+    }
 
-    let edited_content = Link {
-        text: raw_text,
-        title: raw_title,
-        url: raw_url,
-        tags: raw_tags,
-        ..current_content
+    let results = run_checks(&connection, fix)
+        .unwrap_or_else(|e| fail(&format!("FAILED   content checks: {}", e)));
+    let mut problems = Table::new();
+    problems.add_row(row!["CHECK", "SUBJECT", "PROBLEM"]);
+    for result in &results {
+        // A fix can turn up problems that weren't there before, they're counted apart
+        let fixed = result.found.iter()
+            .filter(|problem| !result.remaining.iter().any(|left| left.subject == problem.subject))
+            .count();
+        let new = result.remaining.iter()
+            .filter(|left| !result.found.iter().any(|problem| problem.subject == left.subject))
+            .count();
+        match (result.found.len(), result.remaining.len()) {
+            (0, 0) => println!("ok       {}", result.name),
+            (_, 0) => println!("fixed    {}: {}", result.name, fixed),
+            (found, _) if new > 0 => println!("FAILED   {}: {} found, {} fixed, {} new after fixing", result.name, found, fixed, new),
+            (found, _) if fixed > 0 => println!("FAILED   {}: {} found, {} fixed", result.name, found, fixed),
+            (found, _) if result.fixable && !fix => println!("FAILED   {}: {}, --fix can repair some", result.name, found),
+            (found, _) => println!("FAILED   {}: {}", result.name, found),
+        }
+        let shown = if fix { &result.remaining } else { &result.found };
+        for problem in shown {
+            problems.add_row(row![result.name, problem.subject, problem.detail]);
+        }
+        if state.verbose && fix {
+            for problem in result.found.iter().filter(|problem| !result.remaining.iter().any(|left| left.subject == problem.subject)) {
+                println!("         fixed {}, {}", problem.subject, problem.detail);
+            }
+        }
+    }
+
+    if problems.len() > 1 {
+        problems.printstd();
+        fail("The content has problems");
+    }
+}
+
+fn trash_list(content_type: Option<&str>, format: OutputFormat) {
+    let listed = |listed_type: &str| content_type.map(|content_type| content_type == listed_type).unwrap_or(true);
+    let mut table = Table::new();
+    table.add_row(row!["TYPE", "ID OR KEY", "TITLE", "TRASHED"]);
+    let mut trashed = vec![];
+    if listed("post") {
+        for post in read_trashed_posts() {
+            table.add_row(row!["post", post.id, post.title, post.trashed.unwrap()]);
+            trashed.push(json!({"type": "post", "id": post.id, "title": post.title, "trashed": post.trashed}));
+        }
+    }
+    if listed("link") {
+        for link in read_trashed_links() {
+            table.add_row(row!["link", link.id, link.title, link.trashed.unwrap()]);
+            trashed.push(json!({"type": "link", "id": link.id, "title": link.title, "trashed": link.trashed}));
+        }
+    }
+    if listed("system") {
+        for system in read_trashed_system() {
+            table.add_row(row!["system", system.key, "", system.trashed.unwrap()]);
+            trashed.push(json!({"type": "system", "id": system.key, "title": "", "trashed": system.trashed}));
+        }
+    }
+    if format != OutputFormat::Table {
+        print!("{}", render_list(&trashed, &field_names(&["type", "id", "title", "trashed"]), format));
+        return;
+    }
+    if trashed.is_empty() {
+        println!("The trash is empty");
+        return;
+    }
+    table.printstd();
+    println!("Restore one with: nautilus trash restore <TYPE> <ID OR KEY>");
+}
+
+fn trash_restore(content_type: &str, content_id: &str) {
+    let restored = match content_type {
+        "post" => restore_post(parse_id(content_id)),
+        "link" => restore_link(parse_id(content_id)),
+        "system" => restore_system(content_id),
+        _ => unreachable!(),
     };
+    match restored {
+        Ok(0) => fail(&format!("No {} {} in the trash", content_type, content_id)),
+        Ok(_) => println!("Restored {} {}", content_type, content_id),
+        Err(e) => fail(&format!("Could not restore {} {}: {}", content_type, content_id, e)),
+    }
+}
 
-    let result = update_link(&edited_content).unwrap();
+fn trash_purge(state: State, older_than: &str, yes: bool) {
+    let age = parse_age(older_than).unwrap_or_else(|e| fail(&e));
+    let trashed_before = chrono::Utc::now().naive_utc() - age;
+    if !yes && !confirm(&format!(
+        "Remove everything trashed more than {} ago for good, with its revisions? [y/N] ",
+        older_than
+    )) {
+        println!("Nothing purged.");
+        return;
+    }
+    let purged = purge_trash(trashed_before).unwrap_or_else(|e| fail(&format!("Could not purge the trash: {}", e)));
+    if state.verbose {
+        println!("Posts: {:?}", purged.posts);
+        println!("Links: {:?}", purged.links);
+        println!("System: {:?}", purged.system);
+    }
+    println!(
+        "Purged {} posts, {} links and {} system entries",
+        purged.posts.len(),
+        purged.links.len(),
+        purged.system.len()
+    );
+}
 
+// An age like 30d, 12h or 2w (a bare number is days)
+fn parse_age(age: &str) -> Result<chrono::Duration, String> {
+    let age = age.trim();
+    let (number, unit) = match age.char_indices().find(|(_, character)| !character.is_ascii_digit()) {
+        Some((unit_start, _)) => (&age[..unit_start], &age[unit_start..]),
+        None => (age, "d"),
+    };
+    let number = number.parse::<i64>()
+        .map_err(|_| format!("{} is not an age, use a number with m, h, d or w like 30d", age))?;
+    match unit {
+        "m" => Ok(chrono::Duration::minutes(number)),
+        "h" => Ok(chrono::Duration::hours(number)),
+        "d" => Ok(chrono::Duration::days(number)),
+        "w" => Ok(chrono::Duration::weeks(number)),
+        _ => Err(format!("Unknown age unit {} in {}, use m, h, d or w", unit, age)),
+    }
+}
+
+fn bulk_retag(state: State, args: &ArgMatches) {
+    let add: Vec<String> = split_tags(args.value_of("add").unwrap_or("")).into_iter().map(normalize_tag).collect();
+    let add: Vec<&str> = add.iter().map(String::as_str).collect();
+    let remove = split_tags(args.value_of("remove").unwrap_or(""));
+    if add.is_empty() && remove.is_empty() {
+        fail("Give the tags to change with --add and --remove");
+    }
+    let selection = bulk_selection(args);
+    let items: Vec<BulkItem> = bulk_content_types(args.value_of("content_type")).iter()
+        .flat_map(|content_type| select_content(content_type, &selection).unwrap_or_else(|e| fail(&e.to_string())))
+        .collect();
+    confirm_retag(state, plan_retag(items, &add, &remove), args.is_present("yes"));
+}
+
+// Preview retags, ask (unless told yes) and save them
+fn confirm_retag(state: State, planned: Vec<Retag>, yes: bool) {
+    if planned.is_empty() {
+        println!("Nothing to retag");
+        return;
+    }
+
+    let mut table = Table::new();
+    table.add_row(row!["TYPE", "ID", "TITLE", "TAGS", "NEW TAGS"]);
+    for retag in &planned {
+        table.add_row(row![retag.item.content_type, retag.item.id, retag.item.title, retag.item.tags, retag.new_tags]);
+    }
+    table.printstd();
+    if !yes && !confirm(&format!("Retag these {}? [y/N] ", planned.len())) {
+        println!("Nothing retagged.");
+        return;
+    }
+
+    let retagged = apply_retag(&planned).unwrap_or_else(|e| fail(&e.to_string()));
     if state.verbose {
-        println!("Update post result: {:?}", &result);
+        println!("Retagged: {:?}", planned.iter().map(|retag| format!("{} {}", retag.item.content_type, retag.item.id)).collect::<Vec<String>>());
     }
+    println!("Retagged {} posts and links", retagged);
 }
 
-fn list_system() {
-    let all_system: Vec<System> = read_all_system();
+fn tags_list(format: OutputFormat) {
+    let counts = tag_counts().unwrap_or_else(|e| fail(&e.to_string()));
+    if format != OutputFormat::Table {
+        let counts: Vec<serde_json::Value> = counts.iter()
+            .map(|count| json!({"tag": count.tag, "posts": count.posts, "links": count.links}))
+            .collect();
+        print!("{}", render_list(&counts, &field_names(&["tag", "posts", "links"]), format));
+        return;
+    }
+    if counts.is_empty() {
+        println!("No tags yet");
+        return;
+    }
+    let mut table = Table::new();
+    table.add_row(row!["TAG", "POSTS", "LINKS"]);
+    for count in &counts {
+        table.add_row(row![count.tag, count.posts, count.links]);
+    }
+    table.printstd();
+    let unnormalized = plan_normalize().unwrap_or_else(|e| fail(&e.to_string())).len();
+    if unnormalized > 0 {
+        println!("{} posts and links have tags in mixed case or spacing, tidy them with: nautilus tags normalize", unnormalized);
+    }
+}
+
+fn tags_change(state: State, replaced: &[&str], replacement: Option<&str>, yes: bool) {
+    if let Some(replacement) = replacement {
+        if normalize_tag(replacement).is_empty() || replacement.contains(',') {
+            fail(&format!("\"{}\" is not a tag", replacement));
+        }
+    }
+    let planned = plan_tag_change(replaced, replacement).unwrap_or_else(|e| fail(&e.to_string()));
+    confirm_retag(state, planned, yes);
+}
+
+fn system_list(args: &ArgMatches) {
+    let (format, fields) = output_args("system", args, list_fields("system"));
+    let entries = match (args.value_of("namespace"), args.value_of("prefix")) {
+        (Some(namespace), _) => read_system_namespace(namespace),
+        (None, Some(prefix)) => read_system_by_prefix(prefix),
+        (None, None) => read_all_system(),
+    };
+    if entries.is_empty() && format == OutputFormat::Table {
+        println!("No system entries");
+        return;
+    }
+    print!("{}", render_list(&entries, &fields, format));
+}
+
+fn system_get(path: &str) {
+    match get_system_path(path).unwrap_or_else(|e| fail(&e)) {
+        serde_json::Value::String(value) => println!("{}", value),
+        value => println!("{}", serde_json::to_string_pretty(&value).expect("Could not serialize the value")),
+    }
+}
+
+fn system_set(state: State, path: &str, value: &str, string: bool) {
+    let value = if string { serde_json::Value::String(String::from(value)) } else { parse_value(value) };
+    let entry = set_system_path(path, value).unwrap_or_else(|e| fail(&e));
+    if state.verbose {
+        println!("Saved system key {} version {}", &entry.key, &entry.version);
+    }
+}
+
+fn system_schema(system_key: &str, schema_file: Option<&str>, clear: bool) {
+    if clear {
+        let removed = delete_system_schema(system_key).unwrap_or_else(|e| fail(&e.to_string()));
+        if removed == 0 {
+            fail(&format!("{} has no schema", system_key));
+        }
+        println!("Removed the schema of {}", system_key);
+        return;
+    }
+    match schema_file {
+        Some(schema_file) => {
+            let schema: serde_json::Value = serde_json::from_str(&read_body(schema_file))
+                .unwrap_or_else(|e| fail(&format!("The schema isn't valid JSON: {}", e)));
+            save_system_schema(system_key, &schema).unwrap_or_else(|e| fail(&e));
+            println!("Saved the schema of {}", system_key);
+        }
+        None => match read_system_schema(system_key).unwrap_or_else(|e| fail(&e.to_string())) {
+            Some(schema) => println!("{}", serde_json::to_string_pretty(&schema).expect("Could not serialize the schema")),
+            None => println!("{} has no schema, any value is allowed", system_key),
+        },
+    }
+}
+
+fn bulk_delete(state: State, args: &ArgMatches) {
+    let selection = bulk_selection(args);
+    let items: Vec<BulkItem> = bulk_content_types(args.value_of("content_type")).iter()
+        .flat_map(|content_type| select_content(content_type, &selection).unwrap_or_else(|e| fail(&e.to_string())))
+        .collect();
+    if items.is_empty() {
+        println!("Nothing to delete");
+        return;
+    }
 
     let mut table = Table::new();
-    table.add_row(row!["KEY", "DATA", "TIME"]);
-    for system in all_system {
-        table.add_row(row![&system.key, &system.data, &system.time]);
+    table.add_row(row!["TYPE", "ID", "TITLE", "TAGS"]);
+    for item in &items {
+        table.add_row(row![item.content_type, item.id, item.title, item.tags]);
     }
     table.printstd();
+    if !args.is_present("yes") && !confirm(&format!("Move these {} to the trash? [y/N] ", items.len())) {
+        println!("Nothing deleted.");
+        return;
+    }
+
+    let trashed = apply_delete(&items).unwrap_or_else(|e| fail(&e.to_string()));
+    if state.verbose {
+        println!("Trashed: {:?}", items.iter().map(|item| format!("{} {}", item.content_type, item.id)).collect::<Vec<String>>());
+    }
+    println!("Moved {} posts and links to the trash, restore them with: nautilus trash restore <TYPE> <ID>", trashed);
+}
+
+// The content a bulk command works on, ids and a tag filter can be combined but one is needed
+fn bulk_selection(args: &ArgMatches) -> Selection {
+    let selection = Selection {
+        ids: args.value_of("content_ids").map(|content_ids| parse_id_ranges(content_ids).unwrap_or_else(|e| fail(&e))),
+        tag: args.value_of("tag").map(String::from),
+    };
+    if selection.ids.is_none() && selection.tag.is_none() {
+        fail("Pick the content with id numbers (like 4-20) or --tag");
+    }
+    selection
+}
+
+// Content types as bulk commands take them, singular or plural, with both when none is given
+fn bulk_content_types(content_type: Option<&str>) -> Vec<&'static str> {
+    match content_type {
+        Some("post") | Some("posts") => vec!["post"],
+        Some("link") | Some("links") => vec!["link"],
+        Some(_) => unreachable!(),
+        None => vec!["post", "link"],
+    }
+}
+
+// Ask a yes or no question, anything but yes is no
+fn confirm(question: &str) -> bool {
+    prompt(question).trim().to_lowercase().starts_with('y')
+}
+
+fn check_links(state: State, options: &CheckOptions) {
+    let urls = content_urls();
+    if urls.is_empty() {
+        println!("No URLs to check");
+        return;
+    }
+    println!("Checking {} URLs", urls.len());
+    let checks = check_urls(&urls.iter().map(|(url, _)| url.clone()).collect::<Vec<String>>(), options);
+    save_url_checks(&checks).unwrap_or_else(|e| fail(&format!("Could not save the checks: {}", e)));
+
+    let mut table = Table::new();
+    table.add_row(row!["STATUS", "URL", "REDIRECT OR ERROR", "USED BY"]);
+    let (mut broken, mut redirected) = (0, 0);
+    for (check, (_, sources)) in checks.iter().zip(&urls) {
+        if state.verbose || check.is_broken() || check.is_redirected() {
+            let status = check.status.map(|status| status.to_string()).unwrap_or_else(|| String::from("-"));
+            let detail = check.error.clone().or_else(|| check.redirect.clone()).unwrap_or_default();
+            table.add_row(row![status, check.url, detail, sources.join(", ")]);
+        }
+        if check.is_broken() {
+            broken += 1;
+        } else if check.is_redirected() {
+            redirected += 1;
+        }
+    }
+    if table.len() > 1 {
+        table.printstd();
+    }
+    println!("{} URLs checked, {} broken, {} redirected", checks.len(), broken, redirected);
+    if broken > 0 {
+        fail("Some links are broken");
+    }
+}
+
+// A whole number option like --retries 2
+fn parse_count(count: &str) -> u32 {
+    count.parse::<u32>().unwrap_or_else(|_| fail(&format!("{} is not a whole number", count)))
+}
+
+// Keep what came back from the editor on disk until the database has it
+fn autosave(draft: &Draft) {
+    if let Err(e) = save_draft(draft) {
+        println!("Warning, the draft was not saved: {}", e);
+    }
+}
+
+fn discard_draft(draft: &Draft) {
+    if let Err(e) = remove_draft(draft) {
+        println!("Warning: {}", e);
+    }
+}
+
+// Throw the draft away once the database has the edit.  A save that failed or updated nothing
+// (the content was trashed or purged meanwhile) keeps it for nautilus recover.
+fn settle_draft(draft: &Draft, saved: &diesel::QueryResult<usize>, content: &str) {
+    match saved {
+        Ok(updated) if *updated > 0 => discard_draft(draft),
+        Ok(_) => fail(&format!(
+            "{} was not saved, it was deleted or saved again meanwhile.  The edit is kept as draft {}, see nautilus recover",
+            content,
+            draft.name(),
+        )),
+        Err(e) => fail(&format!(
+            "{} was not saved: {}.  The edit is kept as draft {}, see nautilus recover",
+            content,
+            e,
+            draft.name(),
+        )),
+    }
+}
+
+// List the drafts left behind by failed saves, or resubmit (or discard) one of them
+fn recover(state: State, draft_name: Option<&str>, discard: bool) {
+    let draft_name = match draft_name {
+        Some(draft_name) => draft_name,
+        None => {
+            let drafts = list_drafts().unwrap_or_else(|e| fail(&e));
+            if drafts.is_empty() {
+                println!("No drafts to recover");
+                return;
+            }
+            let mut table = Table::new();
+            table.add_row(row!["DRAFT", "TYPE", "TITLE OR KEY", "SAVED"]);
+            for draft in &drafts {
+                let content_type = match draft.record {
+                    DraftRecord::Post(_) => "post",
+                    DraftRecord::System(_) => "system",
+                };
+                table.add_row(row![draft.name(), content_type, draft.title(), draft.saved]);
+            }
+            table.printstd();
+            println!("Resubmit one with: nautilus recover <DRAFT>");
+            return;
+        }
+    };
+
+    let draft = match find_draft(draft_name).unwrap_or_else(|e| fail(&e)) {
+        Some(draft) => draft,
+        None => fail(&format!("No draft named {}, run nautilus recover to list them", draft_name)),
+    };
+    if discard {
+        remove_draft(&draft).unwrap_or_else(|e| fail(&e));
+        println!("Discarded draft {}", draft.name());
+        return;
+    }
+
+    let summary = submit_draft(&draft).unwrap_or_else(|e| fail(&e.to_string()));
+    if let Some((content_id, reason)) = summary.skipped.first() {
+        fail(&format!(
+            "Draft {} was not saved, {} {}.  Change its version in {} to overwrite anyway, or throw it away with --discard",
+            draft.name(),
+            content_id,
+            reason,
+            draft_file(&draft).map(|path| path.display().to_string()).unwrap_or_default(),
+        ));
+    }
+    discard_draft(&draft);
+    if state.verbose {
+        println!("Inserted: {}", summary.inserted.join(", "));
+        println!("Updated: {}", summary.updated.join(", "));
+    }
+    println!("Recovered draft {}", draft.name());
+}
+
+fn show_post(post_id: i32) {
+    let fields: Vec<String> = all_fields("post").into_iter().map(String::from).collect();
+    print!("{}", render_one(&read_post(post_id), &fields, OutputFormat::Table));
+}
+
+fn show_link(link_id: i32) {
+    let fields: Vec<String> = all_fields("link").into_iter().map(String::from).collect();
+    print!("{}", render_one(&read_link(link_id), &fields, OutputFormat::Table));
+}
+
+fn show_system(key: String) {
+    let fields: Vec<String> = all_fields("system").into_iter().map(String::from).collect();
+    print!("{}", render_one(&read_system(key), &fields, OutputFormat::Table));
+}
+
+fn write_link(state: State) {
+    if state.verbose {
+        println!("Writing Link");
+    }
+    let entered_url = prompt("Link URL: ");
+    let entered_text = prompt("Display text (leave it empty to prefill from the page): ");
+    // The page is only fetched when there's no text, so a link can be written offline
+    let (raw_text, raw_title, raw_url) = if entered_text.trim().is_empty() {
+        println!("Fetching the page...");
+        let (page_url, info) = fetch_page_info(entered_url.trim()).unwrap_or_else(|e| {
+            println!("Warning, nothing was prefilled: {}", e);
+            (String::from(entered_url.trim()), PageInfo::default())
+        });
+        (
+            edit_prompt("Display text: ", info.display_text().unwrap_or("")),
+            edit_prompt("Hover title: ", info.hover_title().unwrap_or("")),
+            edit_prompt("Link URL: ", info.canonical_url.as_deref().unwrap_or(&page_url)),
+        )
+    } else {
+        (entered_text, prompt("Hover title: "), String::from(entered_url.trim()))
+    };
+    let raw_tags = normalize_tags(&edit_prompt("Tags: ", &default_tags()));
+
+    let rawlink = NewLink {
+        text: &raw_text,
+        title: &raw_title,
+        url: &raw_url,
+        tags: &raw_tags,
+    };
+
+    let link = create_link(&rawlink);
+    if state.verbose {
+        println!("\nSaved {} with id {}", &link.text, &link.id);
+    }
+
+}
+
+fn edit_link(state: State, link_id: i32) {
+    if state.verbose {
+        println!("Editing link {}", link_id);
+    }
+
+    let current_content: Link = read_link(link_id);
+
+    let mut raw_text: String = current_content.text.clone();
+    let mut raw_title: String = current_content.title.clone();
+    let mut raw_url: String = current_content.url.clone();
+    let mut raw_tags: String = current_content.tags.clone();
+
+    raw_text = edit_prompt("Edit text: ", &raw_text);
+    raw_title = edit_prompt("Edit title: ", &raw_title);
+    raw_url = edit_prompt("Edit url: ", &raw_url);
+    raw_tags = normalize_tags(&edit_prompt("Edit tags: ", &raw_tags));
+
+    let edited_content = Link {
+        text: raw_text,
+        title: raw_title,
+        url: raw_url,
+        tags: raw_tags,
+        ..current_content
+    };
+
+    match update_link(&edited_content) {
+        Ok(0) if read_trashed_links().iter().any(|link| link.id == link_id) => {
+            fail(&format!("Link {} is in the trash, restore it with: nautilus trash restore link {}", link_id, link_id));
+        }
+        Ok(0) => fail(&format!("Link {} was not saved, it was deleted or saved again meanwhile", link_id)),
+        Ok(updated) => {
+            if state.verbose {
+                println!("Update link result: {:?}", &updated);
+            }
+        }
+        Err(e) => fail(&format!("Link {} was not saved: {}", link_id, e)),
+    }
+}
+
+fn list_system() {
+    let fields: Vec<String> = list_fields("system").into_iter().map(String::from).collect();
+    list_content("system", OutputFormat::Table, &fields);
 }
 
 fn write_system(state: State) {
@@ -523,14 +1980,15 @@ fn write_system(state: State) {
         println!("Writing system entry");
     }
     let raw_key = prompt("System key: ");
-    let raw_data = prompt("System data: ");
+    let raw_data = parse_value(&prompt("System data: "));
+    check_system_data(&raw_key, &raw_data).unwrap_or_else(|e| fail(&e));
 
     let rawlink = NewSystem {
         key: &raw_key,
         data: &raw_data,
     };
 
-    let new_system = create_system(&rawlink);
+    let new_system = create_system(&rawlink).unwrap_or_else(|e| fail(&e));
     if state.verbose {
         println!("Saved {}", &new_system.key);
     }
@@ -538,49 +1996,606 @@ fn write_system(state: State) {
 
 fn edit_system(state: State, system_key: String) {
     let mut system_values = read_system(system_key);
-    system_values.key = edit_prompt("Edit system key: ", &system_values.key);
-    system_values.data = vim_edit(system_values.data);
+    // The value is saved under the key it has now, a new key is a rename afterwards
+    let new_key = edit_prompt("Edit system key: ", &system_values.key);
+    system_values.data = edit_system_data(&system_values.key, &system_values.data);
+    let draft = Draft::new(DraftRecord::System(SystemRecord {
+        key: system_values.key.clone(),
+        data: system_values.data.clone(),
+        time: Some(system_values.time),
+        version: Some(system_values.version),
+        updated: None,
+    }));
+    autosave(&draft);
     let updated_system_result = update_system(&system_values);
+    settle_draft(&draft, &updated_system_result, &format!("System key {}", &system_values.key));
     if state.verbose {
         println!("Updated system: {:?}", &updated_system_result);
     }
+    if new_key.trim() != system_values.key {
+        system_rename(&system_values.key, &new_key);
+    }
 }
 
-fn export_post(state: State, this_post: i32, export_filename: &str) {
+fn system_rename(system_key: &str, new_key: &str) {
+    let renamed = rename_system(system_key, new_key).unwrap_or_else(|e| fail(&e));
+    println!("Renamed system key {} to {}", system_key, &renamed.key);
+}
+
+fn menu_list(format: OutputFormat) {
+    let menus = read_menus().unwrap_or_else(|e| fail(&e.to_string()));
+    if menus.is_empty() && format == OutputFormat::Table {
+        println!("No menus yet, create one with: nautilus menu create <name>");
+        return;
+    }
+    let mut table = Table::new();
+    table.add_row(row!["MENU", "TITLE", "ITEMS", "UPDATED"]);
+    let mut listed = vec![];
+    for menu in &menus {
+        let items = read_menu_items(&menu.name).unwrap_or_else(|e| fail(&e.to_string()));
+        table.add_row(row![menu.name, menu.title, items.len(), menu.updated]);
+        listed.push(json!({"name": menu.name, "title": menu.title, "items": items.len(), "updated": menu.updated}));
+    }
+    if format != OutputFormat::Table {
+        print!("{}", render_list(&listed, &field_names(&["name", "title", "items", "updated"]), format));
+        return;
+    }
+    table.printstd();
+}
+
+fn menu_create(state: State, name: &str, title: &str) {
+    let menu = create_menu(name, title).unwrap_or_else(|e| fail(&e));
     if state.verbose {
-        println!("Exporting post ID {} to filename {}", &this_post, &export_filename);
+        println!("Created menu {}", &menu.name);
     }
-    let post_to_export = read_post(this_post);
+}
 
-    let post_json = json!({
-        "id": &post_to_export.id,
-        "title": &post_to_export.title,
-        "body": &post_to_export.body,
-        "time": &post_to_export.time,
-        "tags": &post_to_export.tags,
-        "summary": &post_to_export.summary,
-    });
+fn menu_show(name: &str, format: &str) {
+    let menu = read_menu_tree(name)
+        .unwrap_or_else(|e| fail(&e.to_string()))
+        .unwrap_or_else(|| fail(&format!("No menu {}", name)));
+    match format {
+        "json" => println!("{}", serde_json::to_string_pretty(&menu).expect("Could not write JSON")),
+        "yaml" => println!("{}", serde_yaml::to_string(&menu).expect("Could not write YAML").trim_end()),
+        _ => {
+            if menu.items.is_empty() {
+                println!("The menu {} has no items, add one with: nautilus menu add {} <label> --url <url>", name, name);
+                return;
+            }
+            let mut table = Table::new();
+            table.add_row(row!["ID", "ITEM", "TARGET"]);
+            add_menu_rows(&mut table, &menu.items, 0);
+            table.printstd();
+        }
+    }
+}
+
+// One row per item, indented under its parent
+fn add_menu_rows(table: &mut Table, items: &[MenuTreeItem], depth: usize) {
+    for item in items {
+        let target = if item.missing { format!("{} (missing)", item.target) } else { item.target.to_string() };
+        table.add_row(row![item.id, format!("{}{}", "  ".repeat(depth), item.label), target]);
+        add_menu_rows(table, &item.items, depth + 1);
+    }
+}
+
+// The target from --url, --post or --node, None when none of them is given
+fn menu_target_args(args: &ArgMatches) -> Option<MenuTarget> {
+    if let Some(url) = args.value_of("url") {
+        Some(MenuTarget::Url { url: String::from(url) })
+    } else if let Some(slug) = args.value_of("post") {
+        Some(MenuTarget::Post { slug: String::from(slug) })
+    } else {
+        args.value_of("node").map(|node| MenuTarget::Node { node: parse_id(node) })
+    }
+}
 
+// --position counts from 1
+fn menu_position_arg(args: &ArgMatches) -> Option<usize> {
+    args.value_of("position").map(|position| match parse_count(position) {
+        0 => fail("Positions start at 1"),
+        position => position as usize - 1,
+    })
+}
+
+fn menu_add(state: State, args: &ArgMatches) {
+    let item = add_menu_item(
+        args.value_of("name").unwrap(),
+        args.value_of("parent").map(parse_id),
+        menu_position_arg(args),
+        args.value_of("label").unwrap(),
+        &menu_target_args(args).unwrap_or(MenuTarget::Heading),
+    ).unwrap_or_else(|e| fail(&e));
     if state.verbose {
-        println!("JSON out: {}", post_json.to_string());
+        println!("Added menu item {} to {}", item.id, &item.menu);
+    }
+}
+
+fn menu_edit(state: State, args: &ArgMatches) {
+    let target = if args.is_present("heading") { Some(MenuTarget::Heading) } else { menu_target_args(args) };
+    let label = args.value_of("label");
+    if label.is_none() && target.is_none() {
+        fail("Nothing to change, use --label, --url, --post, --node or --heading");
+    }
+    let item = edit_menu_item(parse_id(args.value_of("item_id").unwrap()), label, target.as_ref())
+        .unwrap_or_else(|e| fail(&e));
+    if state.verbose {
+        println!("Updated menu item {}: {} {}", item.id, &item.label, item.target());
+    }
+}
+
+fn menu_move(state: State, args: &ArgMatches) {
+    let item_id = parse_id(args.value_of("item_id").unwrap());
+    let parent = match (args.value_of("parent"), args.is_present("top")) {
+        (Some(parent), _) => Some(parse_id(parent)),
+        (None, true) => None,
+        // Without --parent or --top it moves among the items beside it
+        (None, false) => read_menu_item(item_id).unwrap_or_else(|e| fail(&e)).parent,
+    };
+    let item = move_menu_item(item_id, parent, menu_position_arg(args)).unwrap_or_else(|e| fail(&e));
+    if state.verbose {
+        println!("Moved menu item {} to position {}", item.id, item.position + 1);
+    }
+}
+
+fn menu_remove(item_id: i32, yes: bool) {
+    if !yes && !confirm(&format!("Remove menu item {} and the items under it? [y/N] ", item_id)) {
+        println!("Nothing removed");
+        return;
+    }
+    let removed = remove_menu_item(item_id).unwrap_or_else(|e| fail(&e));
+    println!("Removed {} menu items", removed);
+}
+
+fn menu_delete(name: &str, yes: bool) {
+    if !yes && !confirm(&format!("Delete the menu {} and all its items? [y/N] ", name)) {
+        println!("Nothing deleted");
+        return;
+    }
+    match delete_menu(name).unwrap_or_else(|e| fail(&e.to_string())) {
+        0 => fail(&format!("No menu {}", name)),
+        _ => println!("Deleted menu {}", name),
+    }
+}
+
+// Edit a system value as JSON until it parses and matches the schema of its key
+fn edit_system_data(system_key: &str, data: &serde_json::Value) -> serde_json::Value {
+    let mut text = serde_json::to_string_pretty(data).expect("Could not serialize the system data");
+    loop {
+        text = edit_text(&text, "json").unwrap_or_else(|e| fail(&e));
+        let problem = match serde_json::from_str::<serde_json::Value>(&text) {
+            Ok(edited) => match check_system_data(system_key, &edited) {
+                Ok(()) => return edited,
+                Err(e) => e,
+            },
+            Err(e) => format!("The system data isn't valid JSON: {}", e),
+        };
+        println!("{}", problem);
+        if !confirm("Edit it again? [y/N] ") {
+            fail("Nothing was saved");
+        }
+    }
+}
+
+fn export_content(state: State, content_type: &str, content_ids: &str, export_filename: &str) {
+    if state.verbose {
+        println!("Exporting {} {} to filename {}", &content_type, &content_ids, &export_filename);
+    }
+
+    let content_json = match content_type {
+        "post" => {
+            let posts: Vec<Post> = if content_ids == "all" {
+                read_all_posts()
+            } else {
+                let post_ids = parse_ids(content_ids).unwrap_or_else(|e| fail(&e));
+                pick_records(read_all_posts(), &post_ids, |post| post.id, "post")
+            };
+            export_json(posts)
+        }
+        "link" => {
+            let links: Vec<Link> = if content_ids == "all" {
+                read_all_links()
+            } else {
+                let link_ids = parse_ids(content_ids).unwrap_or_else(|e| fail(&e));
+                pick_records(read_all_links(), &link_ids, |link| link.id, "link")
+            };
+            export_json(links)
+        }
+        "system" => {
+            let systems: Vec<System> = if content_ids == "all" {
+                read_all_system()
+            } else {
+                let keys: Vec<String> = content_ids.split(',').map(|key| String::from(key.trim())).collect();
+                pick_records(read_all_system(), &keys, |entry| entry.key.clone(), "system key")
+            };
+            export_json(systems)
+        }
+        _ => unreachable!(),
+    };
+
+    let output = serde_json::to_string_pretty(&content_json)
+        .expect("Could not serialize export content");
+    if state.verbose {
+        println!("JSON out: {}", &output);
     }
     let mut file = fs::File::create(&export_filename)
         .expect("Problem creating export file");
-    file.write_all(post_json.to_string().as_bytes())
+    file.write_all(output.as_bytes())
         .expect("Could not write to export file");
+}
+
+fn import_content(
+    state: State,
+    content_type: &str,
+    import_filename: &str,
+    on_conflict: OnConflict,
+    dry_run: bool,
+) {
+    if state.verbose {
+        println!("Importing filename {} as {} content", &import_filename, &content_type);
+    }
+    let file_string = fs::read_to_string(import_filename)
+        .expect("Could not open the import filename");
 
+    let errors = validate_import(content_type, &file_string);
+    if !errors.is_empty() {
+        for error in &errors {
+            println!("{}:{}: {} {}", &import_filename, error.line, error.pointer, error.message);
+        }
+        fail(&format!("{} problems found, nothing was imported", errors.len()));
+    }
+
+    let result = match content_type {
+        "post" => import_posts(&import_records::<PostRecord>(&file_string), on_conflict, dry_run),
+        "link" => import_links(&import_records::<LinkRecord>(&file_string), on_conflict, dry_run),
+        "system" => import_system(&import_records::<SystemRecord>(&file_string), on_conflict, dry_run),
+        _ => unreachable!(),
+    };
+
+    match result {
+        Ok(summary) => {
+            if dry_run {
+                println!("Dry run, nothing was saved");
+            }
+            println!(
+                "Inserted {}, updated {}, skipped {}",
+                summary.inserted.len(),
+                summary.updated.len(),
+                summary.skipped.len()
+            );
+            if state.verbose {
+                println!("Inserted: {}", summary.inserted.join(", "));
+                println!("Updated: {}", summary.updated.join(", "));
+            }
+            if dry_run && !summary.changes.is_empty() {
+                let mut table = Table::new();
+                table.add_row(row!["RECORD", "FIELD", "DATABASE", "FILE"]);
+                for (record, changes) in summary.changes {
+                    for change in changes {
+                        table.add_row(row![&record, change.field, change.old, change.new]);
+                    }
+                }
+                table.printstd();
+            }
+            if !summary.skipped.is_empty() {
+                let mut table = Table::new();
+                table.add_row(row!["SKIPPED", "REASON"]);
+                for (skipped, reason) in summary.skipped {
+                    table.add_row(row![skipped, reason]);
+                }
+                table.printstd();
+            }
+        }
+        Err(e) => {
+            fail(&e.to_string());
+        }
+    }
+}
+
+fn import_markdown(state: State, import_path: &str, include_drafts: bool, dry_run: bool) {
+    if state.verbose {
+        println!("Importing markdown files from {}", &import_path);
+    }
+
+    let mut files = Vec::new();
+    let mut records = Vec::new();
+    let mut skipped = Table::new();
+    skipped.add_row(row!["SKIPPED FILE", "REASON"]);
+    for markdown_file in find_markdown_files(Path::new(import_path)) {
+        match markdown_record(&markdown_file, include_drafts) {
+            Ok(record) => {
+                files.push(markdown_file);
+                records.push(record);
+            }
+            Err(reason) => {
+                skipped.add_row(row![markdown_file.display(), reason]);
+            }
+        }
+    }
+
+    // All the posts or none, a dry run is the same import rolled back
+    let summary = import_posts(&records, OnConflict::Fail, dry_run).unwrap_or_else(|e| fail(&e.to_string()));
+    for ((markdown_file, record), inserted) in files.iter().zip(&records).zip(&summary.inserted) {
+        if dry_run {
+            let post_time = record.time.map(|time| time.to_string()).unwrap_or_else(|| String::from("now"));
+            println!("Would import {} as \"{}\" ({})", markdown_file.display(), &record.title, post_time);
+        } else if state.verbose {
+            println!("Imported {} as post {}", markdown_file.display(), inserted);
+        }
+    }
+
+    if dry_run {
+        println!("Dry run, {} posts would be imported, skipped {} files", summary.inserted.len(), skipped.len() - 1);
+    } else {
+        println!("Imported {} posts, skipped {} files", summary.inserted.len(), skipped.len() - 1);
+    }
+    if skipped.len() > 1 {
+        skipped.printstd();
+    }
+}
+
+// The post record of a markdown file, or why the file is skipped
+fn markdown_record(markdown_file: &Path, include_drafts: bool) -> Result<PostRecord, String> {
+    if markdown_file.file_name().and_then(|name| name.to_str()) == Some("_index.md") {
+        return Err(String::from("section list page, not a post"));
+    }
+    let contents = fs::read_to_string(markdown_file).map_err(|e| e.to_string())?;
+    let markdown_post = parse_markdown(&contents)?;
+    if markdown_post.draft && !include_drafts {
+        return Err(String::from("draft, use --drafts to import it anyway"));
+    }
+
+    let slug = if markdown_post.slug.is_empty() {
+        slug_from_path(markdown_file)
+    } else {
+        markdown_post.slug.clone()
+    };
+    // Without an updated time the post doesn't look edited since it was written
+    let post_time = markdown_post.time.or_else(|| date_from_path(markdown_file));
+    Ok(PostRecord {
+        id: None,
+        title: markdown_post.title,
+        body: markdown_post.body,
+        time: post_time,
+        tags: Some(markdown_post.tags),
+        summary: Some(markdown_post.summary),
+        version: None,
+        updated: markdown_post.updated.or(post_time),
+        slug: Some(slug),
+    })
+}
+
+fn export_markdown(state: State, out_dir: &str, links_filename: Option<&str>, hugo: bool) {
+    fs::create_dir_all(out_dir).expect("Could not create the markdown directory");
+    let profile = site_profile();
+
+    let mut filenames: Vec<String> = Vec::new();
+    for post in read_all_posts() {
+        let mut filename = markdown_filename(&post);
+        if filenames.contains(&filename) {
+            // Same day and slug as an earlier post, the id keeps it apart
+            filename = format!("{}-{}.md", filename.trim_end_matches(".md"), &post.id);
+        }
+        let path = Path::new(out_dir).join(&filename);
+        if state.verbose {
+            println!("Exporting post ID {} to {}", &post.id, path.display());
+        }
+        fs::write(&path, render_markdown(&post, hugo, profile.timezone()))
+            .expect("Could not write to markdown file");
+        filenames.push(filename);
+    }
+    println!("Exported {} posts to {}", filenames.len(), &out_dir);
+
+    if let Some(links_filename) = links_filename {
+        let all_links = absolute_links(read_all_links(), &profile);
+        if let Some(links_dir) = Path::new(links_filename).parent() {
+            fs::create_dir_all(links_dir).expect("Could not create the links data directory");
+        }
+        fs::write(links_filename, render_links_data(&all_links, profile.timezone()))
+            .expect("Could not write to links data file");
+        println!("Exported {} links to {}", all_links.len(), &links_filename);
+    }
 }
 
-fn import_post(state: State, import_filename: &str) {
+fn import_wxr(
+    state: State,
+    import_filename: &str,
+    blogroll_filename: Option<&str>,
+    include_drafts: bool,
+    dry_run: bool,
+) {
     if state.verbose {
-        println!("Importing filename {} as a piece of Post content", &import_filename);
+        println!("Importing WordPress export {}", &import_filename);
     }
     let file_string = fs::read_to_string(import_filename)
         .expect("Could not open the import filename");
-    let imported_post: Post = serde_json::from_str(&file_string).unwrap();
+    let (wxr_posts, mut skipped) = parse_wxr(&file_string).unwrap_or_else(|e| {
+        fail(&e.to_string());
+    });
+    let wxr_links = match blogroll_filename {
+        Some(blogroll_filename) => {
+            let file_string = fs::read_to_string(blogroll_filename)
+                .expect("Could not open the blogroll filename");
+            parse_blogroll(&file_string).unwrap_or_else(|e| {
+                fail(&e.to_string());
+            })
+        }
+        None => Vec::new(),
+    };
+
+    let mut post_records = Vec::new();
+    for wxr_post in wxr_posts {
+        if wxr_post.status == "trash"
+            || (wxr_post.status != "publish" && !include_drafts) {
+            skipped.push(WxrSkipped {
+                title: wxr_post.title,
+                reason: format!("status {}, use --drafts to import it anyway", &wxr_post.status),
+            });
+            continue;
+        }
+        post_records.push(PostRecord {
+            id: None,
+            title: wxr_post.title,
+            body: wxr_post.body,
+            time: wxr_post.time,
+            tags: Some(wxr_post.tags),
+            summary: Some(wxr_post.summary),
+            version: None,
+            updated: wxr_post.time,
+            slug: Some(wxr_post.slug),
+        });
+    }
+    let link_records: Vec<LinkRecord> = wxr_links.into_iter()
+        .map(|wxr_link| LinkRecord {
+            id: None,
+            text: wxr_link.text,
+            title: wxr_link.title,
+            url: wxr_link.url,
+            tags: Some(wxr_link.tags),
+            time: None,
+            version: None,
+            updated: None,
+        })
+        .collect();
+
+    // The posts and the blogroll are saved all together or not at all
+    let (post_summary, link_summary) = import_posts_and_links(&post_records, &link_records, OnConflict::Fail, dry_run)
+        .unwrap_or_else(|e| fail(&e.to_string()));
+    let mut created = Table::new();
+    created.add_row(row!["TYPE", "TITLE", "TAGS", "TIME"]);
+    for (post_record, post_id) in post_records.iter().zip(&post_summary.inserted) {
+        let post_time = post_record.time.map(|post_time| post_time.to_string()).unwrap_or_default();
+        created.add_row(row!["post", &post_record.title, post_record.tags.as_deref().unwrap_or(""), post_time]);
+        if state.verbose && !dry_run {
+            println!("Saved {} with id {}", &post_record.title, post_id);
+        }
+    }
+    for (link_record, link_id) in link_records.iter().zip(&link_summary.inserted) {
+        created.add_row(row!["link", &link_record.text, link_record.tags.as_deref().unwrap_or(""), ""]);
+        if state.verbose && !dry_run {
+            println!("Saved {} with id {}", &link_record.text, link_id);
+        }
+    }
 
-    let result = update_post(&imported_post).unwrap();
+    if dry_run {
+        println!("Dry run, {} items would be created", created.len() - 1);
+    } else {
+        println!("Created {} items", created.len() - 1);
+    }
+    if created.len() > 1 && (dry_run || state.verbose) {
+        created.printstd();
+    }
+    if !skipped.is_empty() {
+        println!("Skipped {} items", skipped.len());
+        let mut skipped_table = Table::new();
+        skipped_table.add_row(row!["SKIPPED ITEM", "REASON"]);
+        for skipped_item in skipped {
+            skipped_table.add_row(row![skipped_item.title, skipped_item.reason]);
+        }
+        skipped_table.printstd();
+    }
+}
+
+fn import_bookmarks(state: State, import_filename: &str, dry_run: bool) {
     if state.verbose {
-        println!("Imported post result: {}", &result)
+        println!("Importing bookmarks {}", &import_filename);
+    }
+    let file_string = fs::read_to_string(import_filename)
+        .expect("Could not open the import filename");
+    let (bookmarks, mut skipped) = parse_bookmarks(&file_string);
+    let mut links = read_all_links();
+    links.extend(read_trashed_links());
+    let (bookmarks, existing) = skip_existing_links(bookmarks, &links);
+    skipped.extend(existing);
+
+    let saved = save_bookmarks(&bookmarks, dry_run)
+        .unwrap_or_else(|e| fail(&format!("Could not import the bookmarks, nothing was saved: {}", e)));
+    let mut created = Table::new();
+    created.add_row(row!["TEXT", "URL", "TAGS"]);
+    for link in &saved {
+        created.add_row(row![&link.text, &link.url, &link.tags]);
+        if state.verbose && !dry_run {
+            println!("Saved {} with id {}", &link.text, &link.id);
+        }
+    }
+
+    if dry_run {
+        println!("Dry run, {} links would be created", created.len() - 1);
+    } else {
+        println!("Created {} links", created.len() - 1);
+    }
+    if created.len() > 1 && (dry_run || state.verbose) {
+        created.printstd();
+    }
+    if !skipped.is_empty() {
+        println!("Skipped {} bookmarks", skipped.len());
+        let mut skipped_table = Table::new();
+        skipped_table.add_row(row!["SKIPPED BOOKMARK", "REASON"]);
+        for skipped_bookmark in skipped {
+            skipped_table.add_row(row![skipped_bookmark.text, skipped_bookmark.reason]);
+        }
+        skipped_table.printstd();
+    }
+}
+
+fn export_bookmarks(export_filename: Option<&str>) {
+    let all_links = absolute_links(read_all_links(), &site_profile());
+    let html = render_bookmarks(&all_links);
+    match export_filename {
+        Some(export_filename) => {
+            fs::write(export_filename, html).expect("Could not write to bookmark file");
+            println!("Exported {} links to {}", all_links.len(), &export_filename);
+        }
+        None => print!("{}", html),
+    }
+}
+
+// Comma separated id numbers from the command line, like "3" or "3,4,5"
+fn parse_ids(content_ids: &str) -> Result<Vec<i32>, String> {
+    content_ids.split(',')
+        .map(str::trim)
+        .map(|content_id| match content_id {
+            "" => Err(format!("{} has an empty id, separate the ids with single commas", content_ids)),
+            content_id => content_id.parse::<i32>().map_err(|_| format!("{} is not an id number", content_id)),
+        })
+        .collect()
+}
+
+// The records with the wanted ids or keys in the order asked for, fails naming the ones that
+// don't exist (or are in the trash)
+fn pick_records<T, K: PartialEq + std::fmt::Display>(records: Vec<T>, wanted: &[K], key: impl Fn(&T) -> K, content_type: &str) -> Vec<T> {
+    let missing: Vec<String> = wanted.iter()
+        .filter(|wanted_key| !records.iter().any(|record| key(record) == **wanted_key))
+        .map(|wanted_key| wanted_key.to_string())
+        .collect();
+    if !missing.is_empty() {
+        fail(&format!("No {} {}", content_type, missing.join(", ")));
+    }
+    let mut picked: Vec<T> = records.into_iter()
+        .filter(|record| wanted.contains(&key(record)))
+        .collect();
+    picked.sort_by_key(|record| wanted.iter().position(|wanted_key| *wanted_key == key(record)));
+    picked
+}
+
+// A single record is written as a bare object (the original post export format), more than one
+// is written as an array.
+fn export_json<T: Serialize>(records: Vec<T>) -> serde_json::Value {
+    if records.len() == 1 {
+        json!(records[0])
+    } else {
+        json!(records)
+    }
+}
+
+// Import files can hold either a single record object or an array of them, the file has already
+// been checked by validate_import.
+fn import_records<T: DeserializeOwned>(file_string: &str) -> Vec<T> {
+    match serde_json::from_str::<ImportFile<T>>(file_string) {
+        Ok(ImportFile::One(record)) => vec![record],
+        Ok(ImportFile::Many(records)) => records,
+        Err(e) => {
+            fail(&format!("Could not read the import file: {}", e));
+        }
     }
 }
@@ -0,0 +1,359 @@
+// Markdown files with front matter, the content format of static site generators like Hugo and
+// Jekyll.  Front matter is either YAML fenced by "---" lines or TOML fenced by "+++" lines.
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone};
+use chrono_tz::Tz;
+use serde_json::{json, Value};
+use serde_derive::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::models::{Post, Link};
+use crate::split_tags;
+
+/// A post parsed out of a markdown file, not yet saved.
+#[derive(Debug)]
+pub struct MarkdownPost {
+    pub title: String,
+    pub time: Option<NaiveDateTime>,
+    pub updated: Option<NaiveDateTime>,
+    pub tags: String,
+    pub summary: String,
+    pub slug: String,
+    pub draft: bool,
+    pub body: String,
+}
+
+/// Parse a markdown file with YAML or TOML front matter.
+///
+/// Understands the fields render_markdown writes, title, time and updated, and the ones Hugo and
+/// Jekyll share: date, lastmod (or last_modified_at), tags, summary (or description), slug and
+/// draft.  Tags may be a list or a comma or space
+/// separated string, they are saved comma separated like the rest of the posts.  A missing title
+/// or a date that can't be read is an error so the file can be reported and skipped.
+///
+/// ```
+/// use nautilus::markdown::parse_markdown;
+///
+/// let post = parse_markdown("---\ntitle: Hello\ntags: [rust, cli]\n---\nSome *body*\n").unwrap();
+/// assert_eq!(post.title, "Hello");
+/// assert_eq!(post.tags, "rust, cli");
+/// assert_eq!(post.body, "Some *body*\n");
+/// ```
+pub fn parse_markdown(contents: &str) -> Result<MarkdownPost, String> {
+    let (front_matter, body) = split_front_matter(contents)?;
+
+    let title = match front_matter.get("title") {
+        Some(Value::String(title)) if !title.trim().is_empty() => title.trim().to_string(),
+        _ => return Err(String::from("front matter has no title")),
+    };
+
+    Ok(MarkdownPost {
+        title,
+        time: front_matter_date(&front_matter, &["time", "date"])?,
+        updated: front_matter_date(&front_matter, &["updated", "lastmod", "last_modified_at"])?,
+        tags: front_matter_tags(&front_matter),
+        summary: front_matter_string(&front_matter, &["summary", "description"]),
+        slug: front_matter_string(&front_matter, &["slug"]),
+        draft: front_matter.get("draft") == Some(&Value::Bool(true)),
+        body: body.trim_start_matches(|c| c == '\n' || c == '\r').to_string(),
+    })
+}
+
+/// Write a post as a markdown file with YAML front matter.
+///
+/// The front matter has the post's title, time, updated, tags, summary, slug and version.  With
+/// `hugo` the time and updated fields are named date and lastmod instead, the names Hugo reads.
+/// Times are written in `timezone` with its offset, parse_markdown reads either back.
+///
+/// ```
+/// use nautilus::markdown::{parse_markdown, render_markdown};
+/// use nautilus::models::Post;
+/// use chrono::NaiveDate;
+/// use chrono_tz::Tz;
+///
+/// let time = NaiveDate::from_ymd_opt(2020, 4, 24).unwrap().and_hms_opt(21, 9, 3).unwrap();
+/// let post = Post {
+///   id: 1,
+///   title: String::from("Hello"),
+///   body: String::from("Some *body*\n"),
+///   time,
+///   tags: String::from("rust, cli"),
+///   summary: String::new(),
+///   version: 2,
+///   updated: time,
+///   parent: None,
+///   slug: String::from("hello"),
+///   trashed: None,
+/// };
+/// let markdown = render_markdown(&post, false, Tz::UTC);
+/// assert!(markdown.contains("\ntime: \"2020-04-24T21:09:03Z\"\nupdated: "));
+/// assert!(render_markdown(&post, true, Tz::UTC).contains("\ndate: \"2020-04-24T21:09:03Z\"\nlastmod: "));
+/// assert_eq!(parse_markdown(&markdown).unwrap().time, Some(time));
+///
+/// let markdown = render_markdown(&post, false, Tz::America__New_York);
+/// assert!(markdown.contains("\ntime: \"2020-04-24T17:09:03-04:00\"\n"));
+/// assert_eq!(parse_markdown(&markdown).unwrap().time, Some(time));
+/// ```
+pub fn render_markdown(post: &Post, hugo: bool, timezone: Tz) -> String {
+    let (time, updated) = (format_date(&post.time, timezone), format_date(&post.updated, timezone));
+    let front_matter = PostFrontMatter {
+        title: &post.title,
+        time: Some(&time).filter(|_| !hugo),
+        date: Some(&time).filter(|_| hugo),
+        updated: Some(&updated).filter(|_| !hugo),
+        lastmod: Some(&updated).filter(|_| hugo),
+        tags: split_tags(&post.tags),
+        summary: &post.summary,
+        slug: &post.slug,
+        version: post.version,
+    };
+    let yaml = serde_yaml::to_string(&front_matter).expect("Could not write front matter");
+    format!("---\n{}\n---\n\n{}", yaml.trim_start_matches("---\n").trim_end(), post.body)
+}
+
+/// A deterministic Jekyll style file name for a post, "YYYY-MM-DD-slug.md".
+///
+/// Posts without a slug use one made from the title, or the post id when the title has nothing to
+/// make one from.
+pub fn markdown_filename(post: &Post) -> String {
+    let slug = if !post.slug.is_empty() {
+        crate::slugify(&post.slug)
+    } else {
+        crate::slugify(&post.title)
+    };
+    let slug = if slug.is_empty() { format!("post-{}", post.id) } else { slug };
+    format!("{}-{}.md", post.time.format("%Y-%m-%d"), slug)
+}
+
+/// Write links as a YAML list for a static site generator data file (like Hugo's data/links.yaml),
+/// with the times in `timezone`.
+pub fn render_links_data(links: &[Link], timezone: Tz) -> String {
+    let links_data: Vec<LinkData> = links.iter()
+        .map(|link| LinkData {
+            text: &link.text,
+            title: &link.title,
+            url: &link.url,
+            tags: split_tags(&link.tags),
+            date: format_date(&link.time, timezone),
+        })
+        .collect();
+    let yaml = serde_yaml::to_string(&links_data).expect("Could not write links data");
+    format!("{}\n", yaml.trim_start_matches("---\n").trim_end())
+}
+
+/// Parse the dates used in front matter (and Jekyll file names), converted to UTC.
+///
+/// ```
+/// use nautilus::markdown::parse_date;
+///
+/// assert!(parse_date("2020-04-24T21:09:03-04:00").is_some());
+/// assert!(parse_date("2020-04-24 21:09:03 -0400").is_some());
+/// assert!(parse_date("2020-04-24").is_some());
+/// assert!(parse_date("last tuesday").is_none());
+/// ```
+pub fn parse_date(date: &str) -> Option<NaiveDateTime> {
+    let date = date.trim();
+    if let Ok(parsed) = DateTime::parse_from_rfc3339(date) {
+        return Some(parsed.naive_utc());
+    }
+    if let Ok(parsed) = DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S %z") {
+        return Some(parsed.naive_utc());
+    }
+    for format in &["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(parsed) = NaiveDateTime::parse_from_str(date, format) {
+            return Some(parsed);
+        }
+    }
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .ok()
+        .map(|parsed| parsed.and_hms(0, 0, 0))
+}
+
+/// The slug a static site generator would give a file without a slug in its front matter.
+///
+/// Jekyll date prefixes are dropped from the file name and Hugo page bundles (a directory with an
+/// index.md) use the directory name.
+///
+/// ```
+/// use std::path::Path;
+/// use nautilus::markdown::slug_from_path;
+///
+/// assert_eq!(slug_from_path(Path::new("_posts/2020-04-24-adventures-in-rust.md")), "adventures-in-rust");
+/// assert_eq!(slug_from_path(Path::new("content/posts/diving-into-rocket/index.md")), "diving-into-rocket");
+/// ```
+pub fn slug_from_path(path: &Path) -> String {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let stem = if stem == "index" {
+        path.parent()
+            .and_then(|parent| parent.file_name())
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or(stem)
+    } else {
+        stem
+    };
+    match date_from_path(path) {
+        Some(_) => crate::slugify(&stem[11..]),
+        None => crate::slugify(&stem),
+    }
+}
+
+/// The date from a Jekyll style "YYYY-MM-DD-title.md" file name.
+pub fn date_from_path(path: &Path) -> Option<NaiveDateTime> {
+    let stem = path.file_stem()?.to_string_lossy().to_string();
+    if stem.len() < 11 || !stem.is_char_boundary(10) || &stem[10..11] != "-" {
+        return None;
+    }
+    NaiveDate::parse_from_str(&stem[..10], "%Y-%m-%d")
+        .ok()
+        .map(|parsed| parsed.and_hms(0, 0, 0))
+}
+
+/// Find the markdown files (.md or .markdown) at a path, searching directories recursively.
+///
+/// The result is sorted so imports run in the same order every time.
+pub fn find_markdown_files(path: &Path) -> Vec<PathBuf> {
+    let mut markdown_files = Vec::new();
+    collect_markdown_files(path, &mut markdown_files);
+    markdown_files.sort();
+    markdown_files
+}
+
+fn collect_markdown_files(path: &Path, markdown_files: &mut Vec<PathBuf>) {
+    if path.is_dir() {
+        let entries = fs::read_dir(path).expect("Could not read the markdown directory");
+        for entry in entries {
+            let entry = entry.expect("Could not read the markdown directory");
+            collect_markdown_files(&entry.path(), markdown_files);
+        }
+    } else {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("md") | Some("markdown") => markdown_files.push(path.to_path_buf()),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PostFrontMatter<'a> {
+    title: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<&'a String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date: Option<&'a String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    updated: Option<&'a String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lastmod: Option<&'a String>,
+    tags: Vec<&'a str>,
+    summary: &'a str,
+    slug: &'a str,
+    version: i32,
+}
+
+#[derive(Serialize)]
+struct LinkData<'a> {
+    text: &'a str,
+    title: &'a str,
+    url: &'a str,
+    tags: Vec<&'a str>,
+    date: String,
+}
+
+// A UTC time from the database as RFC 3339 in the timezone, with a Z for UTC
+fn format_date(date: &NaiveDateTime, timezone: Tz) -> String {
+    timezone.from_utc_datetime(date).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+// Separate the front matter from the body and parse it into a JSON object, TOML dates are turned
+// into strings on the way.
+fn split_front_matter(contents: &str) -> Result<(serde_json::Map<String, Value>, &str), String> {
+    let contents = contents.trim_start_matches('\u{feff}');
+    let first_line_end = contents.find('\n').unwrap_or_else(|| contents.len());
+    let fence = contents[..first_line_end].trim();
+    if fence != "---" && fence != "+++" {
+        return Err(String::from("no YAML (---) or TOML (+++) front matter"));
+    }
+
+    let rest = &contents[(first_line_end + 1).min(contents.len())..];
+    let mut offset = 0;
+    let (raw_front_matter, body) = loop {
+        if offset >= rest.len() {
+            return Err(String::from("front matter is never closed"));
+        }
+        let line_end = rest[offset..].find('\n').map_or(rest.len(), |end| offset + end + 1);
+        let line = rest[offset..line_end].trim();
+        if line == fence || (fence == "---" && line == "...") {
+            break (&rest[..offset], &rest[line_end..]);
+        }
+        offset = line_end;
+    };
+
+    let front_matter = if fence == "---" {
+        serde_yaml::from_str::<Value>(raw_front_matter)
+            .map_err(|e| format!("bad YAML front matter: {}", e))?
+    } else {
+        toml::from_str::<toml::Value>(raw_front_matter)
+            .map(toml_to_json)
+            .map_err(|e| format!("bad TOML front matter: {}", e))?
+    };
+
+    match front_matter {
+        Value::Object(front_matter) => Ok((front_matter, body)),
+        Value::Null => Ok((serde_json::Map::new(), body)),
+        _ => Err(String::from("front matter is not a set of fields")),
+    }
+}
+
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(string) => json!(string),
+        toml::Value::Integer(integer) => json!(integer),
+        toml::Value::Float(float) => json!(float),
+        toml::Value::Boolean(boolean) => json!(boolean),
+        toml::Value::Datetime(datetime) => json!(datetime.to_string()),
+        toml::Value::Array(array) => Value::Array(array.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => Value::Object(
+            table.into_iter().map(|(key, value)| (key, toml_to_json(value))).collect()
+        ),
+    }
+}
+
+fn front_matter_string(front_matter: &serde_json::Map<String, Value>, keys: &[&str]) -> String {
+    keys.iter()
+        .filter_map(|key| front_matter.get(*key))
+        .filter_map(|value| value.as_str())
+        .map(|value| value.trim().to_string())
+        .next()
+        .unwrap_or_default()
+}
+
+fn front_matter_date(front_matter: &serde_json::Map<String, Value>, keys: &[&str])
+    -> Result<Option<NaiveDateTime>, String> {
+    match keys.iter().filter_map(|key| front_matter.get(*key)).next() {
+        Some(Value::String(date)) => parse_date(date)
+            .map(Some)
+            .ok_or_else(|| format!("could not read the date \"{}\"", date)),
+        Some(date) => Err(format!("could not read the date {}", date)),
+        None => Ok(None),
+    }
+}
+
+fn front_matter_tags(front_matter: &serde_json::Map<String, Value>) -> String {
+    let tags: Vec<String> = match front_matter.get("tags") {
+        Some(Value::Array(tags)) => tags.iter()
+            .filter_map(|tag| tag.as_str())
+            .map(|tag| tag.trim().to_string())
+            .collect(),
+        Some(Value::String(tags)) if tags.contains(',') => tags.split(',')
+            .map(|tag| tag.trim().to_string())
+            .collect(),
+        Some(Value::String(tags)) => tags.split_whitespace()
+            .map(|tag| tag.to_string())
+            .collect(),
+        _ => Vec::new(),
+    };
+    tags.into_iter()
+        .filter(|tag| !tag.is_empty())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
@@ -0,0 +1,406 @@
+// Navigation menus, like "main" or "footer".  A menu has items in order, an item can have items
+// under it, and each item points at a node, a post by its slug or a URL, or at nothing as a
+// heading.  Heads read a menu with read_menu_tree, which also flags the items whose node, post or
+// link is gone or in the trash so they can be left out.
+
+use diesel::prelude::*;
+use crate::serde_derive::Serialize;
+use crate::shared_connection;
+use crate::models::{Menu, MenuItem, NewMenu, NewMenuItem};
+use crate::schema::{menus, menu_items, nodes, posts, links};
+
+/// Where a menu item goes.
+#[derive(Serialize, Clone, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum MenuTarget {
+    /// No target, a heading for the items under it
+    Heading,
+    Node { node: i32 },
+    Post { slug: String },
+    Url { url: String },
+}
+
+impl MenuTarget {
+    // The node, post_slug and url columns for the target
+    fn columns(&self) -> (Option<i32>, Option<&str>, Option<&str>) {
+        match self {
+            MenuTarget::Heading => (None, None, None),
+            MenuTarget::Node { node } => (Some(*node), None, None),
+            MenuTarget::Post { slug } => (None, Some(slug), None),
+            MenuTarget::Url { url } => (None, None, Some(url)),
+        }
+    }
+}
+
+impl std::fmt::Display for MenuTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MenuTarget::Heading => write!(f, "-"),
+            MenuTarget::Node { node } => write!(f, "node {}", node),
+            MenuTarget::Post { slug } => write!(f, "post {}", slug),
+            MenuTarget::Url { url } => write!(f, "{}", url),
+        }
+    }
+}
+
+impl MenuItem {
+    pub fn target(&self) -> MenuTarget {
+        match (self.node, &self.post_slug, &self.url) {
+            (Some(node), _, _) => MenuTarget::Node { node },
+            (_, Some(slug), _) => MenuTarget::Post { slug: slug.clone() },
+            (_, _, Some(url)) => MenuTarget::Url { url: url.clone() },
+            _ => MenuTarget::Heading,
+        }
+    }
+}
+
+/// A menu with its items nested under their parents.
+#[derive(Serialize, Debug)]
+pub struct MenuTree {
+    pub name: String,
+    pub title: String,
+    pub items: Vec<MenuTreeItem>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MenuTreeItem {
+    pub id: i32,
+    pub label: String,
+    pub target: MenuTarget,
+    /// The node is gone or its post or link is gone or in the trash, or no post outside the trash
+    /// has the slug
+    pub missing: bool,
+    pub items: Vec<MenuTreeItem>,
+}
+
+/// Nest menu items under their parents, in position order.  `missing` says which targets point
+/// nowhere.  Items whose parent isn't in the list are left out.
+///
+/// ```
+/// use nautilus::menus::{menu_tree, MenuTarget};
+/// use nautilus::models::MenuItem;
+///
+/// let item = |id, parent, position, label: &str, url: Option<&str>| MenuItem {
+///   id,
+///   menu: String::from("main"),
+///   parent,
+///   position,
+///   label: String::from(label),
+///   node: None,
+///   post_slug: None,
+///   url: url.map(String::from),
+/// };
+/// let items = vec![
+///   item(1, None, 1, "Docs", None),
+///   item(2, None, 0, "Home", Some("/")),
+///   item(3, Some(1), 0, "Guide", Some("/guide")),
+/// ];
+/// let tree = menu_tree(&items, |_| false);
+/// assert_eq!(tree[0].label, "Home");
+/// assert_eq!(tree[1].target, MenuTarget::Heading);
+/// assert_eq!(tree[1].items[0].label, "Guide");
+/// ```
+pub fn menu_tree<F: Fn(&MenuTarget) -> bool>(items: &[MenuItem], missing: F) -> Vec<MenuTreeItem> {
+    fn children<F: Fn(&MenuTarget) -> bool>(items: &[MenuItem], parent: Option<i32>, missing: &F) -> Vec<MenuTreeItem> {
+        let mut under: Vec<&MenuItem> = items.iter().filter(|item| item.parent == parent).collect();
+        under.sort_by_key(|item| (item.position, item.id));
+        under.into_iter().map(|item| {
+            let target = item.target();
+            MenuTreeItem {
+                id: item.id,
+                label: item.label.clone(),
+                missing: missing(&target),
+                target,
+                items: children(items, Some(item.id), missing),
+            }
+        }).collect()
+    }
+    children(items, None, &missing)
+}
+
+/// Read every menu, by name.
+pub fn read_menus() -> QueryResult<Vec<Menu>> {
+    let connection = shared_connection();
+    menus::table.order(menus::name).load::<Menu>(&*connection)
+}
+
+/// Read a menu by name.
+pub fn read_menu(name: &str) -> QueryResult<Option<Menu>> {
+    let connection = shared_connection();
+    menus::table.find(name).get_result::<Menu>(&*connection).optional()
+}
+
+/// Read the items of a menu, by parent and position.
+pub fn read_menu_items(name: &str) -> QueryResult<Vec<MenuItem>> {
+    let connection = shared_connection();
+    menu_items::table
+        .filter(menu_items::menu.eq(name))
+        .order((menu_items::parent, menu_items::position, menu_items::id))
+        .load::<MenuItem>(&*connection)
+}
+
+/// Read a menu as a tree, with its missing targets flagged.  None if there's no menu by that
+/// name.
+///
+/// ```
+/// use nautilus::menus::*;
+///
+/// fn print_main_menu() {
+///   fn print(items: &[MenuTreeItem], depth: usize) {
+///     for item in items.iter().filter(|item| !item.missing) {
+///       println!("{}{} {}", "  ".repeat(depth), item.label, item.target);
+///       print(&item.items, depth + 1);
+///     }
+///   }
+///   if let Some(menu) = read_menu_tree("main").expect("Error reading the menu") {
+///     print(&menu.items, 0);
+///   }
+/// }
+/// ```
+pub fn read_menu_tree(name: &str) -> QueryResult<Option<MenuTree>> {
+    let menu = match read_menu(name)? {
+        Some(menu) => menu,
+        None => return Ok(None),
+    };
+    let items = read_menu_items(name)?;
+    let connection = shared_connection();
+    let node_ids: Vec<i32> = items.iter().filter_map(|item| item.node).collect();
+    let slugs: Vec<&str> = items.iter().filter_map(|item| item.post_slug.as_deref()).collect();
+    let node_children: Vec<(i32, Option<i32>, String)> = nodes::table
+        .select((nodes::id, nodes::child, nodes::child_content_type))
+        .filter(nodes::id.eq_any(&node_ids))
+        .load(&*connection)?;
+    let children_of = |content_type: &str| -> Vec<i32> {
+        node_children.iter()
+            .filter(|(_, _, child_content_type)| child_content_type == content_type)
+            .filter_map(|(_, child, _)| *child)
+            .collect()
+    };
+    let live_posts: Vec<i32> = posts::table
+        .select(posts::id)
+        .filter(posts::id.eq_any(children_of("post")))
+        .filter(posts::trashed.is_null())
+        .load(&*connection)?;
+    let live_links: Vec<i32> = links::table
+        .select(links::id)
+        .filter(links::id.eq_any(children_of("link")))
+        .filter(links::trashed.is_null())
+        .load(&*connection)?;
+    let nodes_with_content: Vec<i32> = node_children.iter()
+        .filter(|(_, child, child_content_type)| match (child, child_content_type.as_str()) {
+            (Some(child), "post") => live_posts.contains(child),
+            (Some(child), "link") => live_links.contains(child),
+            _ => false,
+        })
+        .map(|(id, _, _)| *id)
+        .collect();
+    let posted_slugs: Vec<String> = posts::table
+        .select(posts::slug)
+        .filter(posts::slug.eq_any(&slugs))
+        .filter(posts::trashed.is_null())
+        .load(&*connection)?;
+
+    let items = menu_tree(&items, |target| match target {
+        MenuTarget::Node { node } => !nodes_with_content.contains(node),
+        MenuTarget::Post { slug } => !posted_slugs.contains(slug),
+        _ => false,
+    });
+    Ok(Some(MenuTree { name: menu.name, title: menu.title, items }))
+}
+
+/// Create an empty menu.
+pub fn create_menu(name: &str, title: &str) -> Result<Menu, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(String::from("A menu name can't be empty"));
+    }
+    if read_menu(name).map_err(read_error)?.is_some() {
+        return Err(format!("There's already a menu {}", name));
+    }
+    let connection = shared_connection();
+    diesel::insert_into(menus::table)
+        .values(&NewMenu { name, title })
+        .get_result(&*connection)
+        .map_err(|e| format!("Could not create the menu {}: {}", name, e))
+}
+
+/// Delete a menu and all its items.  Returns the number of menus deleted (0 or 1).
+pub fn delete_menu(name: &str) -> QueryResult<usize> {
+    let connection = shared_connection();
+    diesel::delete(menus::table.find(name)).execute(&*connection)
+}
+
+/// Add an item to a menu, under `parent` (None for the top level) at `position` (from 0, None for
+/// the end).  The items after it move down.
+pub fn add_menu_item(menu: &str, parent: Option<i32>, position: Option<usize>, label: &str, target: &MenuTarget) -> Result<MenuItem, String> {
+    if read_menu(menu).map_err(read_error)?.is_none() {
+        return Err(format!("No menu {}", menu));
+    }
+    let items = read_menu_items(menu).map_err(read_error)?;
+    check_parent(&items, menu, parent)?;
+    check_label(label)?;
+    check_target(target)?;
+
+    let (node, post_slug, url) = target.columns();
+    let mut order = siblings(&items, parent, None);
+    let connection = shared_connection();
+    connection.transaction::<_, diesel::result::Error, _>(|| {
+        let item: MenuItem = diesel::insert_into(menu_items::table)
+            .values(&NewMenuItem { menu, parent, position: order.len() as i32, label: label.trim(), node, post_slug, url })
+            .get_result(&*connection)?;
+        order.insert(position.unwrap_or(order.len()).min(order.len()), item.id);
+        renumber(&connection, &order)?;
+        touch_menu(&connection, menu)?;
+        menu_items::table.find(item.id).get_result(&*connection)
+    }).map_err(|e| format!("Could not add {} to the menu {}: {}", label, menu, e))
+}
+
+/// Change the label or the target of a menu item.
+pub fn edit_menu_item(id: i32, label: Option<&str>, target: Option<&MenuTarget>) -> Result<MenuItem, String> {
+    let mut item = read_menu_item(id)?;
+    if let Some(label) = label {
+        check_label(label)?;
+        item.label = label.trim().to_string();
+    }
+    if let Some(target) = target {
+        check_target(target)?;
+        let (node, post_slug, url) = target.columns();
+        item.node = node;
+        item.post_slug = post_slug.map(String::from);
+        item.url = url.map(String::from);
+    }
+    let connection = shared_connection();
+    connection.transaction::<_, diesel::result::Error, _>(|| {
+        touch_menu(&connection, &item.menu)?;
+        diesel::update(menu_items::table.find(id)).set(&item).get_result(&*connection)
+    }).map_err(|e| format!("Could not change menu item {}: {}", id, e))
+}
+
+/// Move a menu item, with the items under it, to `parent` (None for the top level) at `position`
+/// (from 0, None for the end).  An item can't move under itself or one of its own items.
+pub fn move_menu_item(id: i32, parent: Option<i32>, position: Option<usize>) -> Result<MenuItem, String> {
+    let item = read_menu_item(id)?;
+    let items = read_menu_items(&item.menu).map_err(read_error)?;
+    check_parent(&items, &item.menu, parent)?;
+    let mut ancestor = parent;
+    while let Some(ancestor_id) = ancestor {
+        if ancestor_id == id {
+            return Err(format!("Menu item {} can't go under itself", id));
+        }
+        ancestor = items.iter().find(|other| other.id == ancestor_id).and_then(|other| other.parent);
+    }
+
+    let old_order = siblings(&items, item.parent, Some(id));
+    let mut order = siblings(&items, parent, Some(id));
+    order.insert(position.unwrap_or(order.len()).min(order.len()), id);
+    let connection = shared_connection();
+    connection.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::update(menu_items::table.find(id))
+            .set(menu_items::parent.eq(parent))
+            .execute(&*connection)?;
+        if item.parent != parent {
+            renumber(&connection, &old_order)?;
+        }
+        renumber(&connection, &order)?;
+        touch_menu(&connection, &item.menu)?;
+        menu_items::table.find(id).get_result(&*connection)
+    }).map_err(|e| format!("Could not move menu item {}: {}", id, e))
+}
+
+/// Remove a menu item and the items under it.  Returns the number of items removed.
+pub fn remove_menu_item(id: i32) -> Result<usize, String> {
+    let item = read_menu_item(id)?;
+    let items = read_menu_items(&item.menu).map_err(read_error)?;
+    let order = siblings(&items, item.parent, Some(id));
+    let connection = shared_connection();
+    connection.transaction::<_, diesel::result::Error, _>(|| {
+        let before: i64 = menu_items::table.filter(menu_items::menu.eq(&item.menu)).count().get_result(&*connection)?;
+        diesel::delete(menu_items::table.find(id)).execute(&*connection)?;
+        let after: i64 = menu_items::table.filter(menu_items::menu.eq(&item.menu)).count().get_result(&*connection)?;
+        renumber(&connection, &order)?;
+        touch_menu(&connection, &item.menu)?;
+        Ok((before - after) as usize)
+    }).map_err(|e| format!("Could not remove menu item {}: {}", id, e))
+}
+
+/// Read a menu item by id.
+pub fn read_menu_item(id: i32) -> Result<MenuItem, String> {
+    let connection = shared_connection();
+    menu_items::table.find(id)
+        .get_result::<MenuItem>(&*connection)
+        .optional()
+        .map_err(read_error)?
+        .ok_or_else(|| format!("No menu item {}", id))
+}
+
+fn check_parent(items: &[MenuItem], menu: &str, parent: Option<i32>) -> Result<(), String> {
+    match parent {
+        Some(parent) if !items.iter().any(|item| item.id == parent) => {
+            Err(format!("The menu {} has no item {}", menu, parent))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_label(label: &str) -> Result<(), String> {
+    if label.trim().is_empty() {
+        return Err(String::from("A menu item needs a label"));
+    }
+    Ok(())
+}
+
+// Targets have to exist when they're set, they can go missing later
+fn check_target(target: &MenuTarget) -> Result<(), String> {
+    let connection = shared_connection();
+    match target {
+        MenuTarget::Node { node } => {
+            let found: i64 = nodes::table.filter(nodes::id.eq(node)).count().get_result(&*connection).map_err(read_error)?;
+            if found == 0 {
+                return Err(format!("No node {}", node));
+            }
+        }
+        MenuTarget::Post { slug } => {
+            let found: i64 = posts::table
+                .filter(posts::slug.eq(slug))
+                .filter(posts::trashed.is_null())
+                .count()
+                .get_result(&*connection)
+                .map_err(read_error)?;
+            if found == 0 {
+                return Err(format!("No post has the slug {}", slug));
+            }
+        }
+        MenuTarget::Url { url } if url.trim().is_empty() => return Err(String::from("The URL is empty")),
+        _ => {}
+    }
+    Ok(())
+}
+
+// The ids of the items under a parent in order, leaving one out
+fn siblings(items: &[MenuItem], parent: Option<i32>, leaving_out: Option<i32>) -> Vec<i32> {
+    let mut under: Vec<&MenuItem> = items.iter()
+        .filter(|item| item.parent == parent && Some(item.id) != leaving_out)
+        .collect();
+    under.sort_by_key(|item| (item.position, item.id));
+    under.into_iter().map(|item| item.id).collect()
+}
+
+// Number items 0, 1, 2... in the order given
+fn renumber(connection: &PgConnection, order: &[i32]) -> QueryResult<()> {
+    for (position, id) in order.iter().enumerate() {
+        diesel::update(menu_items::table.find(id))
+            .set(menu_items::position.eq(position as i32))
+            .execute(connection)?;
+    }
+    Ok(())
+}
+
+fn touch_menu(connection: &PgConnection, menu: &str) -> QueryResult<usize> {
+    diesel::update(menus::table.find(menu))
+        .set(menus::updated.eq(chrono::Utc::now().naive_utc()))
+        .execute(connection)
+}
+
+fn read_error(e: diesel::result::Error) -> String {
+    format!("Could not read the menus: {}", e)
+}
@@ -1,4 +1,4 @@
-use crate::schema::{nodes, posts, links, system, node_revisions, post_revisions, link_revisions, system_revisions};
+use crate::schema::{nodes, posts, links, system, menus, menu_items, node_revisions, post_revisions, link_revisions, system_revisions, system_schemas, url_checks};
 use crate::serde_derive::{Serialize, Deserialize};
 
 // @TODO Fix the struct ordering for "post" when we change the name to "article"
@@ -13,7 +13,9 @@ pub struct Post {
     pub summary: String,
     pub version: i32,
     pub updated: chrono::NaiveDateTime,
-    pub parent: i32,
+    pub parent: Option<i32>,
+    pub slug: String,
+    pub trashed: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(Queryable, Identifiable, Insertable, AsChangeset, Serialize, Deserialize, Debug)]
@@ -22,11 +24,30 @@ pub struct PostRevision {
     pub version: i32,
     pub title: String,
     pub body: String,
+    pub summary: String,
+    pub tags: String,
     pub time: chrono::NaiveDateTime,
     pub updated: chrono::NaiveDateTime,
-    pub tags: String,
-    pub summary: String,
-    pub parent: i32,
+    pub parent: Option<i32>,
+    pub slug: String,
+}
+
+// A revision keeps the content as it was before a change, under the version it had then
+impl From<&Post> for PostRevision {
+    fn from(post: &Post) -> PostRevision {
+        PostRevision {
+            id: post.id,
+            version: post.version,
+            title: post.title.clone(),
+            body: post.body.clone(),
+            summary: post.summary.clone(),
+            tags: post.tags.clone(),
+            time: post.time,
+            updated: post.updated,
+            parent: post.parent,
+            slug: post.slug.clone(),
+        }
+    }
 }
 
 #[derive(Insertable)]
@@ -36,6 +57,7 @@ pub struct NewPost<'a> {
     pub body: &'a str,
     pub tags: &'a str,
     pub summary: &'a str,
+    pub slug: &'a str,
 }
 
 
@@ -55,7 +77,7 @@ pub struct Node {
     pub node_last: String,
     pub time: chrono::NaiveDateTime,
     pub updated: chrono::NaiveDateTime,
-    pub child: i32,
+    pub child: Option<i32>,
     pub child_content_type: String,
 }
 
@@ -91,7 +113,7 @@ pub struct NodeRevision {
     pub node_last: String,
     pub time: chrono::NaiveDateTime,
     pub updated: chrono::NaiveDateTime,
-    pub child: i32,
+    pub child: Option<i32>,
     pub child_content_type: String,
 }
 
@@ -105,7 +127,8 @@ pub struct Link {
     pub time: chrono::NaiveDateTime,
     pub version: i32,
     pub updated: chrono::NaiveDateTime,
-    pub parent: i32,
+    pub parent: Option<i32>,
+    pub trashed: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(Queryable, Identifiable, Insertable, AsChangeset, Serialize, Deserialize, Debug)]
@@ -118,7 +141,23 @@ pub struct LinkRevision {
     pub tags: String,
     pub time: chrono::NaiveDateTime,
     pub updated: chrono::NaiveDateTime,
-    pub parent: i32,
+    pub parent: Option<i32>,
+}
+
+impl From<&Link> for LinkRevision {
+    fn from(link: &Link) -> LinkRevision {
+        LinkRevision {
+            id: link.id,
+            version: link.version,
+            text: link.text.clone(),
+            title: link.title.clone(),
+            url: link.url.clone(),
+            tags: link.tags.clone(),
+            time: link.time,
+            updated: link.updated,
+            parent: link.parent,
+        }
+    }
 }
 
 #[derive(Insertable)]
@@ -134,10 +173,11 @@ pub struct NewLink<'a> {
 #[table_name="system"]
 pub struct System {
     pub key: String,
-    pub data: String,
+    pub data: serde_json::Value,
     pub time: chrono::NaiveDateTime,
     pub version: i32,
     pub updated: chrono::NaiveDateTime,
+    pub trashed: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(Queryable, Insertable, AsChangeset, Serialize, Deserialize, Debug)]
@@ -145,16 +185,96 @@ pub struct System {
 pub struct SystemRevision {
     pub key: String,
     pub version: i32,
-    pub data: String,
+    pub data: serde_json::Value,
     pub time: chrono::NaiveDateTime,
     pub updated: chrono::NaiveDateTime,
 }
 
+impl From<&System> for SystemRevision {
+    fn from(entry: &System) -> SystemRevision {
+        SystemRevision {
+            key: entry.key.clone(),
+            version: entry.version,
+            data: entry.data.clone(),
+            time: entry.time,
+            updated: entry.updated,
+        }
+    }
+}
+
 #[derive(Insertable)]
 #[table_name="system"]
 pub struct NewSystem<'a> {
     pub key: &'a str,
-    pub data: &'a str,
+    pub data: &'a serde_json::Value,
+}
+
+// The JSON Schema the value of a system key has to match
+#[derive(Queryable, Insertable, AsChangeset, Serialize, Deserialize, Debug)]
+#[table_name="system_schemas"]
+pub struct SystemSchema {
+    pub key: String,
+    pub schema: serde_json::Value,
+    pub updated: chrono::NaiveDateTime,
+}
+
+// A named navigation menu, its items are in menu_items
+#[derive(Queryable, Insertable, AsChangeset, Serialize, Deserialize, Debug)]
+#[table_name="menus"]
+pub struct Menu {
+    pub name: String,
+    pub title: String,
+    pub time: chrono::NaiveDateTime,
+    pub updated: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="menus"]
+pub struct NewMenu<'a> {
+    pub name: &'a str,
+    pub title: &'a str,
+}
+
+// An item of a menu, at most one of node, post_slug and url is set
+#[derive(Queryable, Identifiable, AsChangeset, Serialize, Deserialize, Clone, Debug)]
+#[table_name="menu_items"]
+#[changeset_options(treat_none_as_null="true")]
+pub struct MenuItem {
+    pub id: i32,
+    pub menu: String,
+    pub parent: Option<i32>,
+    pub position: i32,
+    pub label: String,
+    pub node: Option<i32>,
+    pub post_slug: Option<String>,
+    pub url: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name="menu_items"]
+pub struct NewMenuItem<'a> {
+    pub menu: &'a str,
+    pub parent: Option<i32>,
+    pub position: i32,
+    pub label: &'a str,
+    pub node: Option<i32>,
+    pub post_slug: Option<&'a str>,
+    pub url: Option<&'a str>,
+}
+
+// The last check of a URL from the links or the post bodies
+#[derive(Queryable, Insertable, AsChangeset, Serialize, Deserialize, Clone, Debug)]
+#[table_name="url_checks"]
+#[changeset_options(treat_none_as_null="true")]
+pub struct UrlCheck {
+    pub url: String,
+    /// The HTTP status of the last response, None when there was no response
+    pub status: Option<i32>,
+    /// Where the URL ended up when it redirects
+    pub redirect: Option<String>,
+    /// Why there was no response, like a timeout
+    pub error: Option<String>,
+    pub checked: chrono::NaiveDateTime,
 }
 
 // Trying to place the relevant enum here
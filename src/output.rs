@@ -0,0 +1,181 @@
+// Output formats for listing and showing content.  Records are rendered through their serde field
+// names so the names are the same in every format, pick fields with a list like ["id", "title"].
+
+use prettytable::{Table, Row, Cell};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use serde_json::Value;
+use std::str::FromStr;
+
+/// The formats content can be printed in, table is for people and the rest are for scripts.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Jsonl,
+    Yaml,
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<OutputFormat, String> {
+        match format {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "yaml" => Ok(OutputFormat::Yaml),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!("Unknown format {}, use table, json, jsonl, yaml or csv", format)),
+        }
+    }
+}
+
+/// Every field of a content type (post, link or system) in the order they are shown.
+pub fn all_fields(content_type: &str) -> Vec<&'static str> {
+    match content_type {
+        "post" => vec!["id", "title", "slug", "summary", "tags", "time", "updated", "version", "parent", "body"],
+        "link" => vec!["id", "text", "title", "url", "tags", "time", "updated", "version", "parent"],
+        "system" => vec!["key", "data", "time", "updated", "version"],
+        _ => panic!("Unknown content type {}", content_type),
+    }
+}
+
+/// The fields of a content type shown in lists when none are picked.
+pub fn list_fields(content_type: &str) -> Vec<&'static str> {
+    match content_type {
+        "post" => vec!["id", "title", "summary", "tags", "time"],
+        "link" => vec!["id", "text", "url", "title", "time"],
+        "system" => vec!["key", "data", "time"],
+        _ => panic!("Unknown content type {}", content_type),
+    }
+}
+
+/// Check picked fields (a comma separated list like "id,title") against a content type.
+///
+/// ```
+/// use nautilus::output::parse_fields;
+///
+/// assert_eq!(parse_fields("link", "id, url").unwrap(), vec!["id", "url"]);
+/// assert!(parse_fields("link", "id,body").is_err());
+/// ```
+pub fn parse_fields(content_type: &str, fields: &str) -> Result<Vec<String>, String> {
+    let available = all_fields(content_type);
+    let mut picked = Vec::new();
+    for field in fields.split(',').map(|field| field.trim()).filter(|field| !field.is_empty()) {
+        if !available.contains(&field) {
+            return Err(format!("{} has no field {}, use {}", content_type, field, available.join(",")));
+        }
+        picked.push(field.to_string());
+    }
+    Ok(picked)
+}
+
+/// Render a list of records with the picked fields.
+///
+/// ```
+/// use nautilus::output::{render_list, OutputFormat};
+/// use serde_json::json;
+///
+/// let records = vec![json!({"id": 1, "title": "Hi, there"}), json!({"id": 2, "title": "Bye"})];
+/// let fields = vec![String::from("id"), String::from("title")];
+/// assert_eq!(render_list(&records, &fields, OutputFormat::Jsonl), "{\"id\":1,\"title\":\"Hi, there\"}\n{\"id\":2,\"title\":\"Bye\"}\n");
+/// assert_eq!(render_list(&records, &fields, OutputFormat::Csv), "id,title\n1,\"Hi, there\"\n2,Bye\n");
+///
+/// // The fields come out in the order they were picked
+/// let fields = vec![String::from("title"), String::from("id")];
+/// assert_eq!(render_list(&records[1..], &fields, OutputFormat::Jsonl), "{\"title\":\"Bye\",\"id\":2}\n");
+/// assert_eq!(render_list(&records[1..], &fields, OutputFormat::Yaml), "---\n- title: Bye\n  id: 2\n");
+/// ```
+pub fn render_list<T: Serialize>(records: &[T], fields: &[String], format: OutputFormat) -> String {
+    let records: Vec<PickedFields> = records.iter()
+        .map(|record| pick_fields(record, fields))
+        .collect();
+
+    match format {
+        OutputFormat::Table => {
+            let mut table = Table::new();
+            table.add_row(Row::new(fields.iter().map(|field| Cell::new(&field.to_uppercase())).collect()));
+            for record in &records {
+                table.add_row(Row::new(fields.iter().map(|field| Cell::new(&plain(record.get(field)))).collect()));
+            }
+            table.to_string()
+        }
+        OutputFormat::Json => format!("{}\n", serde_json::to_string_pretty(&records).expect("Could not write JSON")),
+        OutputFormat::Jsonl => records.iter()
+            .map(|record| format!("{}\n", serde_json::to_string(record).expect("Could not write JSON")))
+            .collect(),
+        OutputFormat::Yaml => format!("{}\n", serde_yaml::to_string(&records).expect("Could not write YAML").trim_end()),
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            writer.write_record(fields).expect("Could not write CSV");
+            for record in &records {
+                writer.write_record(fields.iter().map(|field| plain(record.get(field))))
+                    .expect("Could not write CSV");
+            }
+            String::from_utf8(writer.into_inner().expect("Could not write CSV")).expect("Could not write CSV")
+        }
+    }
+}
+
+/// Render one record with the picked fields, the table format lists the fields down the side.
+pub fn render_one<T: Serialize>(record: &T, fields: &[String], format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Table => {
+            let record = pick_fields(record, fields);
+            let mut table = Table::new();
+            table.add_row(Row::new(vec![Cell::new("FIELD"), Cell::new("VALUE")]));
+            for field in fields {
+                table.add_row(Row::new(vec![Cell::new(&field.to_uppercase()), Cell::new(&plain(record.get(field)))]));
+            }
+            table.to_string()
+        }
+        OutputFormat::Json => format!(
+            "{}\n",
+            serde_json::to_string_pretty(&pick_fields(record, fields)).expect("Could not write JSON")
+        ),
+        OutputFormat::Yaml => format!(
+            "{}\n",
+            serde_yaml::to_string(&pick_fields(record, fields)).expect("Could not write YAML").trim_end()
+        ),
+        OutputFormat::Jsonl | OutputFormat::Csv => render_list(&[record], fields, format),
+    }
+}
+
+// The picked fields of a record in the order they were picked, a serde_json Map would sort them
+struct PickedFields(Vec<(String, Value)>);
+
+impl PickedFields {
+    fn get(&self, field: &str) -> &Value {
+        self.0.iter()
+            .find(|(picked, _)| picked == field)
+            .map(|(_, value)| value)
+            .unwrap_or(&Value::Null)
+    }
+}
+
+impl Serialize for PickedFields {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (field, value) in &self.0 {
+            map.serialize_entry(field, value)?;
+        }
+        map.end()
+    }
+}
+
+fn pick_fields<T: Serialize>(record: &T, fields: &[String]) -> PickedFields {
+    let record = serde_json::to_value(record).expect("Could not read record fields");
+    PickedFields(fields.iter()
+        .map(|field| (field.clone(), record.get(field).cloned().unwrap_or(Value::Null)))
+        .collect())
+}
+
+// Strings without their JSON quotes for tables and CSV
+fn plain(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
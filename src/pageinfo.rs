@@ -0,0 +1,244 @@
+// Details of a web page for prefilling a new link: the <title>, the OpenGraph title and description
+// and the canonical URL.  The HTML is scanned for the few tags needed rather than parsed properly,
+// which is plenty for the head of a page.
+
+use std::time::Duration;
+
+/// What a page says about itself, anything it doesn't say is None.
+#[derive(Default, PartialEq, Debug)]
+pub struct PageInfo {
+    /// The <title>
+    pub title: Option<String>,
+    /// The og:title
+    pub og_title: Option<String>,
+    /// The og:description, or the description meta tag
+    pub description: Option<String>,
+    /// The canonical link, or the og:url
+    pub canonical_url: Option<String>,
+}
+
+impl PageInfo {
+    /// The link display text, the OpenGraph title or else the page title.
+    pub fn display_text(&self) -> Option<&str> {
+        self.og_title.as_deref().or_else(|| self.title.as_deref())
+    }
+
+    /// The link hover title, the description or else the display text.
+    pub fn hover_title(&self) -> Option<&str> {
+        self.description.as_deref().or_else(|| self.display_text())
+    }
+}
+
+/// Read the page details from the HTML of a page.  A relative canonical URL is resolved against
+/// the URL of the page.
+///
+/// ```
+/// use nautilus::pageinfo::parse_page_info;
+///
+/// let html = r#"<html><head>
+///   <TITLE>Rust &amp; You | Blog</TITLE>
+///   <meta property="og:title" content="Rust &amp; You">
+///   <meta name=description content='Why &quot;Rust&quot;?'>
+///   <link rel="canonical" href="/posts/rust-and-you">
+/// </head><body><title>Not this one</title></body></html>"#;
+/// let info = parse_page_info(html, "https://blog.example.com/posts/rust-and-you?ref=feed");
+/// assert_eq!(info.title.as_deref(), Some("Rust & You | Blog"));
+/// assert_eq!(info.display_text(), Some("Rust & You"));
+/// assert_eq!(info.hover_title(), Some("Why \"Rust\"?"));
+/// assert_eq!(info.canonical_url.as_deref(), Some("https://blog.example.com/posts/rust-and-you"));
+/// ```
+pub fn parse_page_info(html: &str, page_url: &str) -> PageInfo {
+    let mut info = PageInfo::default();
+    let mut og_title = None;
+    let mut og_url = None;
+    let mut meta_description = None;
+    let mut og_description = None;
+    let lowercase = html.to_ascii_lowercase();
+
+    let mut position = 0;
+    while let Some(tag_start) = lowercase[position..].find('<').map(|found| position + found) {
+        let (name, attributes, tag_end) = read_tag(html, tag_start);
+        position = tag_end;
+        let attribute = |wanted: &str| attributes.iter()
+            .find(|(attribute_name, _)| attribute_name == wanted)
+            .map(|(_, value)| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+        match name.as_str() {
+            "title" if info.title.is_none() => {
+                if let Some(title_end) = lowercase[tag_end..].find("</title").map(|found| tag_end + found) {
+                    info.title = Some(decode_entities(&html[tag_end..title_end]))
+                        .map(|title| title.split_whitespace().collect::<Vec<&str>>().join(" "))
+                        .filter(|title| !title.is_empty());
+                    position = title_end;
+                }
+            }
+            "meta" => {
+                let key = attribute("property").or_else(|| attribute("name")).map(|key| key.to_lowercase());
+                let content = attribute("content").map(|content| decode_entities(&content));
+                match key.as_deref() {
+                    Some("og:title") => og_title = og_title.or(content),
+                    Some("og:description") => og_description = og_description.or(content),
+                    Some("description") => meta_description = meta_description.or(content),
+                    Some("og:url") => og_url = og_url.or(content),
+                    _ => {}
+                }
+            }
+            "link" => {
+                let canonical = attribute("rel")
+                    .map(|rel| rel.to_lowercase().split_whitespace().any(|rel| rel == "canonical"))
+                    .unwrap_or(false);
+                if canonical && info.canonical_url.is_none() {
+                    info.canonical_url = attribute("href").map(|href| decode_entities(&href));
+                }
+            }
+            "body" | "/head" => break,
+            _ => {}
+        }
+    }
+
+    info.og_title = og_title;
+    info.description = og_description.or(meta_description);
+    info.canonical_url = info.canonical_url
+        .or(og_url)
+        .map(|canonical_url| resolve_url(page_url, &canonical_url));
+    info
+}
+
+/// Fetch a page and read its details, the page URL given is where redirects ended up.
+///
+/// ```
+/// use nautilus::pageinfo::fetch_page_info;
+///
+/// fn prefill_a_link() {
+///   let (page_url, info) = fetch_page_info("https://www.rust-lang.org/").unwrap();
+///   println!("{:?} at {}", info.display_text(), page_url);
+/// }
+/// ```
+pub fn fetch_page_info(url: &str) -> Result<(String, PageInfo), String> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(10))
+        .user_agent(concat!("nautilus/", env!("CARGO_PKG_VERSION")))
+        .build();
+    let response = agent.get(url).call()
+        .map_err(|e| format!("Could not fetch {}: {}", url, e))?;
+    let page_url = String::from(response.get_url());
+    if !response.content_type().contains("html") {
+        return Ok((page_url, PageInfo::default()));
+    }
+    let html = response.into_string()
+        .map_err(|e| format!("Could not read {}: {}", url, e))?;
+    let info = parse_page_info(&html, &page_url);
+    Ok((page_url, info))
+}
+
+// The lowercase name and the attributes of the tag starting at a '<', and where the tag ends
+pub(crate) fn read_tag(html: &str, tag_start: usize) -> (String, Vec<(String, String)>, usize) {
+    let bytes = html.as_bytes();
+    let mut position = tag_start + 1;
+    let word_end = |from: usize| html[from..]
+        .find(|character: char| character.is_whitespace() || character == '>' || character == '=' || character == '<')
+        .map(|found| from + found)
+        .unwrap_or(html.len());
+
+    let name_end = word_end(position);
+    let name = html[position..name_end].to_lowercase();
+    position = name_end;
+    let mut attributes = Vec::new();
+    while position < bytes.len() {
+        match bytes[position] {
+            b'>' => return (name, attributes, position + 1),
+            b'<' => break,
+            byte if byte.is_ascii_whitespace() || byte == b'/' => position += 1,
+            _ => {
+                let attribute_end = word_end(position);
+                let attribute_name = html[position..attribute_end].to_lowercase();
+                position = attribute_end;
+                let mut value = String::new();
+                if bytes.get(position) == Some(&b'=') {
+                    position += 1;
+                    match bytes.get(position) {
+                        Some(&quote) if quote == b'"' || quote == b'\'' => {
+                            let value_end = html[position + 1..].find(quote as char)
+                                .map(|found| position + 1 + found)
+                                .unwrap_or(html.len());
+                            value = String::from(&html[position + 1..value_end]);
+                            position = (value_end + 1).min(html.len());
+                        }
+                        _ => {
+                            let value_end = word_end(position);
+                            value = String::from(&html[position..value_end]);
+                            position = value_end;
+                        }
+                    }
+                }
+                attributes.push((attribute_name, value));
+            }
+        }
+    }
+    (name, attributes, position)
+}
+
+// Turn the common HTML entities back into text
+pub(crate) fn decode_entities(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = text;
+    while let Some(ampersand) = rest.find('&') {
+        decoded.push_str(&rest[..ampersand]);
+        rest = &rest[ampersand..];
+        let entity_end = match rest.find(';') {
+            Some(entity_end) if entity_end <= 10 => entity_end,
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let entity = &rest[1..entity_end];
+        let character = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ if entity.starts_with("#x") || entity.starts_with("#X") => {
+                u32::from_str_radix(&entity[2..], 16).ok().and_then(std::char::from_u32)
+            }
+            _ if entity.starts_with('#') => entity[1..].parse::<u32>().ok().and_then(std::char::from_u32),
+            _ => None,
+        };
+        match character {
+            Some(character) => {
+                decoded.push(character);
+                rest = &rest[entity_end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+// A URL from a page made absolute, "/about" on https://example.com/blog/ is https://example.com/about
+fn resolve_url(page_url: &str, url: &str) -> String {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return String::from(url);
+    }
+    let scheme_end = page_url.find("://").map(|found| found + 3).unwrap_or(0);
+    let origin_end = page_url[scheme_end..].find('/').map(|found| scheme_end + found).unwrap_or(page_url.len());
+    if let Some(without_scheme) = url.strip_prefix("//") {
+        format!("{}{}", &page_url[..scheme_end], without_scheme)
+    } else if url.starts_with('/') {
+        format!("{}{}", &page_url[..origin_end], url)
+    } else {
+        let path = page_url.split(|character| character == '?' || character == '#').next().unwrap_or(page_url);
+        let directory_end = path.rfind('/').filter(|slash| *slash >= origin_end).map(|slash| slash + 1);
+        match directory_end {
+            Some(directory_end) => format!("{}{}", &path[..directory_end], url),
+            None => format!("{}/{}", &page_url[..origin_end], url),
+        }
+    }
+}
@@ -8,7 +8,7 @@ table! {
         tags -> Varchar,
         time -> Timestamptz,
         updated -> Timestamptz,
-        parent -> Int4,
+        parent -> Nullable<Int4>,
     }
 }
 
@@ -22,7 +22,30 @@ table! {
         time -> Timestamptz,
         version -> Int4,
         updated -> Timestamptz,
-        parent -> Int4,
+        parent -> Nullable<Int4>,
+        trashed -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    menu_items (id) {
+        id -> Int4,
+        menu -> Varchar,
+        parent -> Nullable<Int4>,
+        position -> Int4,
+        label -> Varchar,
+        node -> Nullable<Int4>,
+        post_slug -> Nullable<Varchar>,
+        url -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    menus (name) {
+        name -> Varchar,
+        title -> Varchar,
+        time -> Timestamptz,
+        updated -> Timestamptz,
     }
 }
 
@@ -42,7 +65,7 @@ table! {
         node_last -> Text,
         time -> Timestamptz,
         updated -> Timestamptz,
-        child -> Int4,
+        child -> Nullable<Int4>,
         child_content_type -> Varchar,
     }
 }
@@ -63,7 +86,7 @@ table! {
         node_last -> Text,
         time -> Timestamptz,
         updated -> Timestamptz,
-        child -> Int4,
+        child -> Nullable<Int4>,
         child_content_type -> Varchar,
     }
 }
@@ -78,7 +101,8 @@ table! {
         tags -> Varchar,
         time -> Timestamptz,
         updated -> Timestamptz,
-        parent -> Int4,
+        parent -> Nullable<Int4>,
+        slug -> Varchar,
     }
 }
 
@@ -92,17 +116,20 @@ table! {
         summary -> Varchar,
         version -> Int4,
         updated -> Timestamptz,
-        parent -> Int4,
+        parent -> Nullable<Int4>,
+        slug -> Varchar,
+        trashed -> Nullable<Timestamptz>,
     }
 }
 
 table! {
     system (key) {
         key -> Varchar,
-        data -> Varchar,
+        data -> Jsonb,
         time -> Timestamptz,
         version -> Int4,
         updated -> Timestamptz,
+        trashed -> Nullable<Timestamptz>,
     }
 }
 
@@ -110,19 +137,47 @@ table! {
     system_revisions (key, version) {
         key -> Varchar,
         version -> Int4,
-        data -> Varchar,
+        data -> Jsonb,
         time -> Timestamptz,
         updated -> Timestamptz,
     }
 }
 
+table! {
+    system_schemas (key) {
+        key -> Varchar,
+        schema -> Jsonb,
+        updated -> Timestamptz,
+    }
+}
+
+table! {
+    url_checks (url) {
+        url -> Varchar,
+        status -> Nullable<Int4>,
+        redirect -> Nullable<Varchar>,
+        error -> Nullable<Varchar>,
+        checked -> Timestamptz,
+    }
+}
+
+joinable!(link_revisions -> nodes (parent));
+joinable!(menu_items -> menus (menu));
+joinable!(links -> nodes (parent));
+joinable!(post_revisions -> nodes (parent));
+joinable!(posts -> nodes (parent));
+
 allow_tables_to_appear_in_same_query!(
     link_revisions,
     links,
+    menu_items,
+    menus,
     node_revisions,
     nodes,
     post_revisions,
     posts,
     system,
     system_revisions,
+    system_schemas,
+    url_checks,
 );
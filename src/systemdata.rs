@@ -0,0 +1,372 @@
+// System values are JSON, read and changed by a path like "site.title" or "menu.items[0].url".  The
+// start of a path is the system key, the longest key that exists wins so a key can have dots in
+// it ("site.title" is the whole value of a "site.title" key, or the title field of a "site" key).
+// A key can have a JSON Schema its value has to match, checked whenever the value is saved.
+
+use diesel::prelude::*;
+use serde_json::{Map, Value};
+use std::fmt;
+use crate::shared_connection;
+use crate::models::{System, SystemRevision, SystemSchema};
+use crate::schema::{system, system_revisions, system_schemas};
+use crate::validate::{unsupported_keywords, validate_json};
+
+/// One step of a path into a JSON value.
+#[derive(Clone, PartialEq, Debug)]
+pub enum PathSegment {
+    Field(String),
+    Index(usize),
+}
+
+impl fmt::Display for PathSegment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PathSegment::Field(field) => write!(f, ".{}", field),
+            PathSegment::Index(index) => write!(f, "[{}]", index),
+        }
+    }
+}
+
+/// Why a system value couldn't be read as a type.
+#[derive(Debug)]
+pub enum SystemReadError {
+    /// No such key, or it's in the trash
+    Missing(String),
+    Database(diesel::result::Error),
+    Deserialize(serde_json::Error),
+}
+
+impl From<diesel::result::Error> for SystemReadError {
+    fn from(error: diesel::result::Error) -> SystemReadError {
+        SystemReadError::Database(error)
+    }
+}
+
+impl From<serde_json::Error> for SystemReadError {
+    fn from(error: serde_json::Error) -> SystemReadError {
+        SystemReadError::Deserialize(error)
+    }
+}
+
+impl fmt::Display for SystemReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SystemReadError::Missing(system_key) => write!(f, "No system key {}", system_key),
+            SystemReadError::Database(error) => write!(f, "Could not read the system entries: {}", error),
+            SystemReadError::Deserialize(error) => write!(f, "The system value doesn't fit: {}", error),
+        }
+    }
+}
+
+/// Parse a path of dotted fields and [index] steps.
+///
+/// ```
+/// use nautilus::systemdata::{parse_path, PathSegment};
+///
+/// assert_eq!(parse_path("menu.items[0].url").unwrap(), vec![
+///   PathSegment::Field(String::from("menu")),
+///   PathSegment::Field(String::from("items")),
+///   PathSegment::Index(0),
+///   PathSegment::Field(String::from("url")),
+/// ]);
+/// assert!(parse_path("").unwrap().is_empty());
+/// assert!(parse_path("menu..items").is_err());
+/// assert!(parse_path("items[first]").is_err());
+/// ```
+pub fn parse_path(path: &str) -> Result<Vec<PathSegment>, String> {
+    let mut segments = Vec::new();
+    let mut rest = path;
+    while !rest.is_empty() {
+        if let Some(index_start) = rest.strip_prefix('[') {
+            let index_end = index_start.find(']').ok_or_else(|| format!("Unclosed [ in the path {}", path))?;
+            let index = index_start[..index_end].trim().parse::<usize>()
+                .map_err(|_| format!("{} is not an index in the path {}", &index_start[..index_end], path))?;
+            segments.push(PathSegment::Index(index));
+            rest = &index_start[index_end + 1..];
+        } else {
+            let field_start = match rest.strip_prefix('.') {
+                Some(field_start) if !segments.is_empty() => field_start,
+                _ if segments.is_empty() => rest,
+                _ => return Err(format!("Expected . or [ in the path {} before {}", path, rest)),
+            };
+            let field_end = field_start.find(['.', '[']).unwrap_or(field_start.len());
+            if field_end == 0 {
+                return Err(format!("Empty field name in the path {}", path));
+            }
+            segments.push(PathSegment::Field(String::from(&field_start[..field_end])));
+            rest = &field_start[field_end..];
+        }
+    }
+    Ok(segments)
+}
+
+/// The value at a path in a JSON value, None if something on the way is missing.
+///
+/// ```
+/// use nautilus::systemdata::{parse_path, value_at};
+/// use serde_json::json;
+///
+/// let menu = json!({"items": [{"url": "/about"}]});
+/// assert_eq!(value_at(&menu, &parse_path("items[0].url").unwrap()), Some(&json!("/about")));
+/// assert_eq!(value_at(&menu, &parse_path("items[1].url").unwrap()), None);
+/// ```
+pub fn value_at<'a>(value: &'a Value, path: &[PathSegment]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, segment| match segment {
+        PathSegment::Field(field) => value.get(field.as_str()),
+        PathSegment::Index(index) => value.get(*index),
+    })
+}
+
+/// Put a value at a path in a JSON value.  Missing fields are added and null is replaced by an
+/// object or array as needed, an array can grow by one at its end.
+///
+/// ```
+/// use nautilus::systemdata::{parse_path, set_value_at};
+/// use serde_json::json;
+///
+/// let mut menu = json!({"title": "Main"});
+/// set_value_at(&mut menu, &parse_path("items[0].url").unwrap(), json!("/about")).unwrap();
+/// assert_eq!(menu, json!({"title": "Main", "items": [{"url": "/about"}]}));
+/// assert!(set_value_at(&mut menu, &parse_path("items[5]").unwrap(), json!("/far")).is_err());
+/// assert!(set_value_at(&mut menu, &parse_path("title.text").unwrap(), json!("Main")).is_err());
+/// ```
+pub fn set_value_at(value: &mut Value, path: &[PathSegment], new_value: Value) -> Result<(), String> {
+    let mut current = value;
+    for (position, segment) in path.iter().enumerate() {
+        let at = path[..position].iter().map(|segment| segment.to_string()).collect::<String>();
+        let at = if at.is_empty() { String::from("the value") } else { at.trim_start_matches('.').to_string() };
+        current = match segment {
+            PathSegment::Field(field) => {
+                if current.is_null() {
+                    *current = Value::Object(Map::new());
+                }
+                match current {
+                    Value::Object(fields) => fields.entry(field.clone()).or_insert(Value::Null),
+                    other => return Err(format!("{} is {}, not an object", at, describe(other))),
+                }
+            }
+            PathSegment::Index(index) => {
+                if current.is_null() {
+                    *current = Value::Array(Vec::new());
+                }
+                match current {
+                    Value::Array(items) => {
+                        if *index == items.len() {
+                            items.push(Value::Null);
+                        }
+                        let length = items.len();
+                        items.get_mut(*index)
+                            .ok_or_else(|| format!("{} has {} items, there's no item {} to set", at, length, index))?
+                    }
+                    other => return Err(format!("{} is {}, not an array", at, describe(other))),
+                }
+            }
+        };
+    }
+    *current = new_value;
+    Ok(())
+}
+
+/// A value from the command line, JSON when it is JSON and a string otherwise.
+///
+/// ```
+/// use nautilus::systemdata::parse_value;
+/// use serde_json::json;
+///
+/// assert_eq!(parse_value("/about"), json!("/about"));
+/// assert_eq!(parse_value("42"), json!(42));
+/// assert_eq!(parse_value(r#"{"url": "/about"}"#), json!({"url": "/about"}));
+/// ```
+pub fn parse_value(text: &str) -> Value {
+    serde_json::from_str(text.trim()).unwrap_or_else(|_| Value::String(String::from(text)))
+}
+
+/// The system entry a path starts with, the longest key that matches, and the rest of the path.
+///
+/// ```
+/// use nautilus::systemdata::find_system_path;
+///
+/// fn find_the_menu() {
+///   if let Some((entry, path)) = find_system_path("menu.items[0].url").unwrap() {
+///     println!("{} then {:?}", entry.key, path);
+///   }
+/// }
+/// ```
+pub fn find_system_path(path: &str) -> Result<Option<(System, Vec<PathSegment>)>, String> {
+    // Every place the key could end, after a field and before a . or [
+    let mut candidates: Vec<&str> = path.match_indices(['.', '['])
+        .map(|(key_end, _)| &path[..key_end])
+        .filter(|key| !key.is_empty())
+        .collect();
+    candidates.push(path);
+
+    let connection = shared_connection();
+    let entries = system::table
+        .filter(system::key.eq_any(&candidates))
+        .filter(system::trashed.is_null())
+        .load::<System>(&*connection)
+        .map_err(|e| format!("Could not read the system entries: {}", e))?;
+    let entry = match entries.into_iter().max_by_key(|entry| entry.key.len()) {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+    let rest = parse_path(path[entry.key.len()..].trim_start_matches('.'))?;
+    Ok(Some((entry, rest)))
+}
+
+/// The value at a path, starting with a system key.
+///
+/// ```
+/// use nautilus::systemdata::get_system_path;
+///
+/// fn site_title() {
+///   println!("{}", get_system_path("site.title").unwrap());
+/// }
+/// ```
+pub fn get_system_path(path: &str) -> Result<Value, String> {
+    let (entry, rest) = find_system_path(path)?
+        .ok_or_else(|| format!("No system entry for {}", path))?;
+    value_at(&entry.data, &rest)
+        .cloned()
+        .ok_or_else(|| format!("{} has nothing at {}", entry.key, path))
+}
+
+/// Put a value at a path, starting with a system key, and save the entry as a new version with the
+/// old one kept as a revision.  The new value has to match the schema of the key.
+///
+/// ```
+/// use nautilus::systemdata::set_system_path;
+/// use serde_json::json;
+///
+/// fn link_the_about_page() {
+///   set_system_path("menu.items[0].url", json!("/about")).unwrap();
+/// }
+/// ```
+pub fn set_system_path(path: &str, new_value: Value) -> Result<System, String> {
+    let (entry, rest) = find_system_path(path)?
+        .ok_or_else(|| format!("No system entry for {}, create it with \"nautilus create system\"", path))?;
+    let mut data = entry.data.clone();
+    set_value_at(&mut data, &rest, new_value)?;
+    check_system_data(&entry.key, &data)?;
+
+    let connection = shared_connection();
+    connection.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::insert_into(system_revisions::table)
+            .values(&SystemRevision::from(&entry))
+            .execute(&*connection)?;
+        diesel::update(system::table.filter(system::key.eq(&entry.key)).filter(system::version.eq(entry.version)))
+            .set((system::data.eq(&data), system::version.eq(entry.version + 1), system::updated.eq(chrono::Utc::now().naive_utc())))
+            .get_result::<System>(&*connection)
+    }).map_err(|e| format!("Could not save {}: {}", entry.key, e))
+}
+
+/// The JSON Schema of a system key, if it has one.
+pub fn read_system_schema(system_key: &str) -> QueryResult<Option<Value>> {
+    let connection = shared_connection();
+
+    system_schemas::table
+        .find(system_key)
+        .select(system_schemas::schema)
+        .get_result::<Value>(&*connection)
+        .optional()
+}
+
+/// Set the JSON Schema of a system key, replacing the one it had.  A value the key already has
+/// must match the new schema, and a schema with keywords the validator doesn't check is refused.
+///
+/// ```
+/// use nautilus::systemdata::save_system_schema;
+/// use serde_json::json;
+///
+/// fn the_site_needs_a_title() {
+///   save_system_schema("site", &json!({"type": "object", "required": ["title"]})).unwrap();
+/// }
+/// ```
+pub fn save_system_schema(system_key: &str, schema: &Value) -> Result<(), String> {
+    if !schema.is_object() && !schema.is_boolean() {
+        return Err(String::from("A JSON Schema is an object (or true or false)"));
+    }
+    let unsupported = unsupported_keywords(schema);
+    if !unsupported.is_empty() {
+        return Err(format!("The schema of {} uses keywords that aren't checked: {}", system_key, unsupported.join(", ")));
+    }
+    let connection = shared_connection();
+    let current = system::table
+        .find(system_key)
+        .select(system::data)
+        .get_result::<Value>(&*connection)
+        .optional()
+        .map_err(|e| format!("Could not read {}: {}", system_key, e))?;
+    if let Some(current) = current {
+        schema_errors(system_key, schema, &current)?;
+    }
+
+    let record = SystemSchema {
+        key: String::from(system_key),
+        schema: schema.clone(),
+        updated: chrono::Utc::now().naive_utc(),
+    };
+    diesel::insert_into(system_schemas::table)
+        .values(&record)
+        .on_conflict(system_schemas::key)
+        .do_update()
+        .set(&record)
+        .execute(&*connection)
+        .map(|_| ())
+        .map_err(|e| format!("Could not save the schema of {}: {}", system_key, e))
+}
+
+/// Remove the JSON Schema of a system key, returns the number of schemas removed (0 or 1).
+pub fn delete_system_schema(system_key: &str) -> QueryResult<usize> {
+    let connection = shared_connection();
+
+    diesel::delete(system_schemas::table.find(system_key)).execute(&*connection)
+}
+
+/// Check a value against the JSON Schema of its system key, a key without a schema takes any
+/// value.
+///
+/// ```
+/// use nautilus::systemdata::check_system_data;
+/// use serde_json::json;
+///
+/// fn check_the_site() {
+///   if let Err(e) = check_system_data("site", &json!({"title": 42})) {
+///     println!("{}", e);
+///   }
+/// }
+/// ```
+pub fn check_system_data(system_key: &str, data: &Value) -> Result<(), String> {
+    let schema = read_system_schema(system_key)
+        .map_err(|e| format!("Could not read the schema of {}: {}", system_key, e))?;
+    match schema {
+        Some(schema) => schema_errors(system_key, &schema, data),
+        None => Ok(()),
+    }
+}
+
+fn schema_errors(system_key: &str, schema: &Value, data: &Value) -> Result<(), String> {
+    if schema == &Value::Bool(false) {
+        return Err(format!("The schema of {} allows no value", system_key));
+    }
+    let errors = validate_json(schema, data);
+    if errors.is_empty() {
+        return Ok(());
+    }
+    let problems: Vec<String> = errors.iter()
+        .map(|error| format!("{}{} {}", system_key, error.pointer.replace('/', "."), error.message))
+        .collect();
+    Err(format!("The value doesn't match the schema of {}: {}", system_key, problems.join(", ")))
+}
+
+// "a string", "an object" and so on for messages
+fn describe(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
@@ -0,0 +1,143 @@
+// Tags across posts and links.  Tags are kept in the comma separated tags field of each post and
+// link, lowercase with single spaces ("rust, command line"), and compared in that normalized form
+// so "Rust" and " rust" are the same tag.  Changes go through the bulk retag, one transaction with
+// a revision of every post and link changed.
+
+use diesel::prelude::*;
+use std::collections::BTreeMap;
+use crate::split_tags;
+use crate::bulk::{select_content, BulkItem, Retag, Selection};
+
+/// A tag and how many posts and links have it.
+#[derive(Debug)]
+pub struct TagCount {
+    pub tag: String,
+    pub posts: usize,
+    pub links: usize,
+}
+
+/// The normalized form of one tag, lowercase with the whitespace trimmed and collapsed.
+///
+/// ```
+/// use nautilus::tags::normalize_tag;
+///
+/// assert_eq!(normalize_tag("  Command \t Line "), "command line");
+/// ```
+pub fn normalize_tag(tag: &str) -> String {
+    tag.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Normalize a comma separated tags field, every tag normalized once, in the order given.
+///
+/// ```
+/// use nautilus::tags::normalize_tags;
+///
+/// assert_eq!(normalize_tags("Rust,cli , rust,,"), "rust, cli");
+/// ```
+pub fn normalize_tags(tags: &str) -> String {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in split_tags(tags).into_iter().map(normalize_tag) {
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized.join(", ")
+}
+
+/// Replace tags in a comma separated tags field, with another tag in the same place or with
+/// nothing.  The field comes back normalized.
+///
+/// ```
+/// use nautilus::tags::replace_tags;
+///
+/// assert_eq!(replace_tags("old, Rust, older", &["old", "older"], Some("new")), "new, rust");
+/// assert_eq!(replace_tags("old, rust", &["OLD"], None), "rust");
+/// ```
+pub fn replace_tags(tags: &str, replaced: &[&str], replacement: Option<&str>) -> String {
+    let replaced: Vec<String> = replaced.iter().map(|tag| normalize_tag(tag)).collect();
+    let replacement = replacement.map(normalize_tag).unwrap_or_default();
+    let swapped: Vec<String> = split_tags(tags).into_iter()
+        .map(|tag| {
+            let tag = normalize_tag(tag);
+            if replaced.contains(&tag) {
+                replacement.clone()
+            } else {
+                tag
+            }
+        })
+        .collect();
+    normalize_tags(&swapped.join(","))
+}
+
+/// Every tag on posts and links outside the trash with how often it's used, by tag.
+///
+/// ```
+/// use nautilus::tags::tag_counts;
+///
+/// fn list_the_tags() {
+///   for count in tag_counts().unwrap() {
+///     println!("{} {} {}", count.tag, count.posts, count.links);
+///   }
+/// }
+/// ```
+pub fn tag_counts() -> QueryResult<Vec<TagCount>> {
+    let mut counts: BTreeMap<String, TagCount> = BTreeMap::new();
+    for item in all_content()? {
+        for tag in split_tags(&normalize_tags(&item.tags)) {
+            let count = counts.entry(String::from(tag))
+                .or_insert_with(|| TagCount { tag: String::from(tag), posts: 0, links: 0 });
+            match item.content_type {
+                "post" => count.posts += 1,
+                _ => count.links += 1,
+            }
+        }
+    }
+    Ok(counts.into_values().collect())
+}
+
+/// The retags replacing tags on every post and link that has one of them, for a rename or merge
+/// (a replacement) or a delete (no replacement).  Apply them with bulk::apply_retag.
+///
+/// ```
+/// use nautilus::bulk::apply_retag;
+/// use nautilus::tags::plan_tag_change;
+///
+/// fn rename_a_tag() {
+///   let planned = plan_tag_change(&["golang"], Some("go")).unwrap();
+///   apply_retag(&planned).unwrap();
+/// }
+/// ```
+pub fn plan_tag_change(replaced: &[&str], replacement: Option<&str>) -> QueryResult<Vec<Retag>> {
+    let replaced_normalized: Vec<String> = replaced.iter().map(|tag| normalize_tag(tag)).collect();
+    Ok(all_content()?
+        .into_iter()
+        .filter(|item| split_tags(&item.tags).into_iter().any(|tag| replaced_normalized.contains(&normalize_tag(tag))))
+        .map(|item| {
+            let new_tags = replace_tags(&item.tags, replaced, replacement);
+            Retag { item, new_tags }
+        })
+        .filter(|planned| planned.new_tags != planned.item.tags)
+        .collect())
+}
+
+/// The retags normalizing every tags field that isn't normalized yet.
+pub fn plan_normalize() -> QueryResult<Vec<Retag>> {
+    Ok(all_content()?
+        .into_iter()
+        .map(|item| {
+            let new_tags = normalize_tags(&item.tags);
+            Retag { item, new_tags }
+        })
+        .filter(|planned| planned.new_tags != planned.item.tags)
+        .collect())
+}
+
+// Every post and link outside the trash
+fn all_content() -> QueryResult<Vec<BulkItem>> {
+    let mut items = select_content("post", &Selection::default())?;
+    items.extend(select_content("link", &Selection::default())?);
+    Ok(items)
+}
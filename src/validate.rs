@@ -0,0 +1,434 @@
+// Validating import files against the JSON Schema of the import/export format, and system values
+// against the schema of their key.  The import schema is made from the import record structs, the
+// validator covers the parts of JSON Schema those use plus the common keywords for values (enum,
+// minimum and maximum, minLength and maxLength, additionalProperties).  Schemas using any other
+// keyword are refused when saved, so a keyword is never quietly ignored.
+
+use schemars::{schema_for, JsonSchema};
+use serde_derive::{Serialize, Deserialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use crate::import::{PostRecord, LinkRecord, SystemRecord};
+
+/// An import file holds either one record or an array of them.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ImportFile<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+/// A problem found in an import file, the line it starts on and the JSON pointer to the field.
+#[derive(Debug)]
+pub struct ValidationError {
+    pub line: usize,
+    pub pointer: String,
+    pub message: String,
+}
+
+/// The JSON Schema of the import/export file for a content type (post, link or system).
+///
+/// ```
+/// use nautilus::validate::import_schema;
+///
+/// let schema = import_schema("link");
+/// assert_eq!(schema["definitions"]["LinkRecord"]["required"][0], "text");
+/// ```
+pub fn import_schema(content_type: &str) -> Value {
+    let mut schema = match content_type {
+        "post" => schema_for!(ImportFile<PostRecord>),
+        "link" => schema_for!(ImportFile<LinkRecord>),
+        "system" => schema_for!(ImportFile<SystemRecord>),
+        _ => panic!("Unknown content type {}", content_type),
+    };
+    schema.schema.metadata().title = Some(format!("Nautilus {} import and export file", content_type));
+    serde_json::to_value(&schema).expect("Could not serialize the JSON Schema")
+}
+
+/// Check an import file against the schema for its content type and report every problem found.
+///
+/// A file that isn't JSON at all gets a single error at the line the parser gave up on.
+///
+/// ```
+/// use nautilus::validate::validate_import;
+///
+/// let errors = validate_import("post", "[\n  {\"title\": \"Hi\", \"body\": \"There\"},\n  {\"title\": 3}\n]");
+/// assert_eq!(errors.len(), 2);
+/// assert_eq!(errors[0].line, 3);
+/// assert_eq!(errors[0].pointer, "/1/body");
+/// ```
+pub fn validate_import(content_type: &str, file_string: &str) -> Vec<ValidationError> {
+    let instance: Value = match serde_json::from_str(file_string) {
+        Ok(instance) => instance,
+        Err(e) => return vec![ValidationError {
+            line: e.line(),
+            pointer: String::new(),
+            message: e.to_string(),
+        }],
+    };
+
+    let schema = import_schema(content_type);
+    let mut errors = Vec::new();
+    validate_value(&schema, &schema, &instance, "", &mut errors);
+
+    let lines = pointer_lines(file_string);
+    errors.into_iter()
+        .map(|(pointer, message)| ValidationError {
+            line: pointer_line(&lines, &pointer),
+            pointer,
+            message,
+        })
+        .collect()
+}
+
+/// Check a JSON value against a JSON Schema and report every problem found, by JSON pointer.
+///
+/// ```
+/// use nautilus::validate::validate_json;
+/// use serde_json::json;
+///
+/// let schema = json!({
+///   "type": "object",
+///   "required": ["title"],
+///   "properties": {
+///     "title": {"type": "string", "minLength": 1},
+///     "theme": {"enum": ["light", "dark"]}
+///   },
+///   "additionalProperties": false
+/// });
+/// assert!(validate_json(&schema, &json!({"title": "My blog", "theme": "dark"})).is_empty());
+/// let errors = validate_json(&schema, &json!({"title": "", "theme": "blue", "colour": 3}));
+/// let errors: Vec<(&str, &str)> = errors.iter().map(|error| (error.pointer.as_str(), error.message.as_str())).collect();
+/// assert_eq!(errors, vec![
+///   ("/colour", "is not an allowed field"),
+///   ("/theme", r#"should be one of "light", "dark""#),
+///   ("/title", "should be at least 1 characters long"),
+/// ]);
+/// ```
+pub fn validate_json(schema: &Value, instance: &Value) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    validate_value(schema, schema, instance, "", &mut errors);
+    errors.into_iter()
+        .map(|(pointer, message)| ValidationError { line: 1, pointer, message })
+        .collect()
+}
+
+/// The keywords of a JSON Schema that validate_json doesn't check, by JSON pointer.  Only local
+/// $refs ("#/definitions/...") and the date-time and int32 formats are checked, keywords that are
+/// only notes (title, description, default, examples) are fine.
+///
+/// ```
+/// use nautilus::validate::unsupported_keywords;
+/// use serde_json::json;
+///
+/// let schema = json!({
+///   "type": "object",
+///   "properties": {
+///     "path": {"type": "string", "pattern": "^/"},
+///     "tags": {"type": "array", "items": {"type": "string"}, "uniqueItems": true}
+///   }
+/// });
+/// assert_eq!(unsupported_keywords(&schema), vec!["/properties/path/pattern", "/properties/tags/uniqueItems"]);
+/// assert!(unsupported_keywords(&json!({"type": "string", "format": "date-time"})).is_empty());
+/// ```
+pub fn unsupported_keywords(schema: &Value) -> Vec<String> {
+    let mut unsupported = Vec::new();
+    find_unsupported(schema, "", &mut unsupported);
+    unsupported
+}
+
+fn find_unsupported(schema: &Value, pointer: &str, unsupported: &mut Vec<String>) {
+    let keywords = match schema {
+        Value::Object(keywords) => keywords,
+        _ => return,
+    };
+    for (keyword, value) in keywords {
+        let keyword_pointer = format!("{}/{}", pointer, escape_pointer(keyword));
+        let supported = match keyword.as_str() {
+            "$schema" | "$id" | "$comment" | "title" | "description" | "default" | "examples" => true,
+            "type" | "enum" | "minimum" | "maximum" | "minLength" | "maxLength" | "required" => true,
+            "format" => matches!(value.as_str(), Some("date-time") | Some("int32")),
+            "$ref" => matches!(value.as_str(), Some(reference) if reference.starts_with('#')),
+            "properties" | "definitions" | "$defs" => {
+                for (name, subschema) in value.as_object().into_iter().flatten() {
+                    find_unsupported(subschema, &format!("{}/{}", keyword_pointer, escape_pointer(name)), unsupported);
+                }
+                true
+            }
+            "anyOf" => {
+                for (index, subschema) in value.as_array().into_iter().flatten().enumerate() {
+                    find_unsupported(subschema, &format!("{}/{}", keyword_pointer, index), unsupported);
+                }
+                true
+            }
+            "additionalProperties" | "items" => {
+                find_unsupported(value, &keyword_pointer, unsupported);
+                // A list of item schemas (one per position) isn't checked
+                !value.is_array()
+            }
+            _ => false,
+        };
+        if !supported {
+            unsupported.push(keyword_pointer);
+        }
+    }
+}
+
+fn validate_value(root: &Value, schema: &Value, instance: &Value, pointer: &str, errors: &mut Vec<(String, String)>) {
+    let schema = resolve(root, schema);
+    if *schema == Value::Bool(false) {
+        errors.push((pointer.to_string(), String::from("is not allowed")));
+        return;
+    }
+
+    if let Some(Value::Array(branches)) = schema.get("anyOf") {
+        let mut branch_errors: Vec<Vec<(String, String)>> = Vec::new();
+        for branch in branches {
+            let mut these_errors = Vec::new();
+            validate_value(root, branch, instance, pointer, &mut these_errors);
+            if these_errors.is_empty() {
+                return;
+            }
+            branch_errors.push(these_errors);
+        }
+        // Report the problems of the branch that at least has the right type
+        match branches.iter().position(|branch| type_matches(resolve(root, branch), instance)) {
+            Some(matching) => errors.append(&mut branch_errors[matching]),
+            None => {
+                let allowed: Vec<String> = branches.iter().map(|branch| type_names(resolve(root, branch))).collect();
+                errors.push((pointer.to_string(), format!("should be {}, found {}", allowed.join(" or "), json_type(instance))));
+            }
+        }
+        return;
+    }
+
+    if !type_matches(schema, instance) {
+        errors.push((pointer.to_string(), format!("should be {}, found {}", type_names(schema), json_type(instance))));
+        return;
+    }
+
+    if let (Some(Value::String(format)), Value::String(_)) = (schema.get("format"), instance) {
+        if format.ends_with("date-time")
+            && serde_json::from_value::<chrono::NaiveDateTime>(instance.clone()).is_err() {
+            errors.push((pointer.to_string(), String::from("should be a date and time like 2020-04-24T21:09:03")));
+        }
+    }
+    if let (Some(Value::String(format)), Some(number)) = (schema.get("format"), instance.as_i64()) {
+        if format == "int32" && (number < i64::from(i32::MIN) || number > i64::from(i32::MAX)) {
+            errors.push((pointer.to_string(), String::from("is too large for a 32 bit number")));
+        }
+    }
+
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(instance) {
+            let allowed: Vec<String> = allowed.iter().map(|value| value.to_string()).collect();
+            errors.push((pointer.to_string(), format!("should be one of {}", allowed.join(", "))));
+        }
+    }
+    if let Some(number) = instance.as_f64() {
+        if let Some(minimum) = schema.get("minimum").and_then(|minimum| minimum.as_f64()).filter(|minimum| number < *minimum) {
+            errors.push((pointer.to_string(), format!("should be at least {}", minimum)));
+        }
+        if let Some(maximum) = schema.get("maximum").and_then(|maximum| maximum.as_f64()).filter(|maximum| number > *maximum) {
+            errors.push((pointer.to_string(), format!("should be at most {}", maximum)));
+        }
+    }
+    if let Value::String(string) = instance {
+        let length = string.chars().count() as u64;
+        if let Some(min_length) = schema.get("minLength").and_then(|min_length| min_length.as_u64()).filter(|min_length| length < *min_length) {
+            errors.push((pointer.to_string(), format!("should be at least {} characters long", min_length)));
+        }
+        if let Some(max_length) = schema.get("maxLength").and_then(|max_length| max_length.as_u64()).filter(|max_length| length > *max_length) {
+            errors.push((pointer.to_string(), format!("should be at most {} characters long", max_length)));
+        }
+    }
+
+    if let Value::Object(fields) = instance {
+        if let Some(Value::Array(required)) = schema.get("required") {
+            for field in required.iter().filter_map(|field| field.as_str()) {
+                if !fields.contains_key(field) {
+                    errors.push((format!("{}/{}", pointer, field), String::from("is required")));
+                }
+            }
+        }
+        let no_properties = serde_json::Map::new();
+        let properties = match schema.get("properties") {
+            Some(Value::Object(properties)) => properties,
+            _ => &no_properties,
+        };
+        for (field, value) in fields {
+            let field_pointer = format!("{}/{}", pointer, escape_pointer(field));
+            match (properties.get(field), schema.get("additionalProperties")) {
+                (Some(property), _) => validate_value(root, property, value, &field_pointer, errors),
+                (None, Some(Value::Bool(false))) => errors.push((field_pointer, String::from("is not an allowed field"))),
+                (None, Some(additional @ Value::Object(_))) => validate_value(root, additional, value, &field_pointer, errors),
+                (None, _) => {}
+            }
+        }
+    }
+
+    if let (Value::Array(items), Some(item_schema)) = (instance, schema.get("items")) {
+        for (index, item) in items.iter().enumerate() {
+            validate_value(root, item_schema, item, &format!("{}/{}", pointer, index), errors);
+        }
+    }
+}
+
+fn resolve<'a>(root: &'a Value, schema: &'a Value) -> &'a Value {
+    match schema.get("$ref").and_then(|reference| reference.as_str()) {
+        Some(reference) => root.pointer(reference.trim_start_matches('#')).unwrap_or(schema),
+        None => schema,
+    }
+}
+
+fn type_matches(schema: &Value, instance: &Value) -> bool {
+    let allowed: Vec<&str> = match schema.get("type") {
+        Some(Value::String(allowed)) => vec![allowed.as_str()],
+        Some(Value::Array(allowed)) => allowed.iter().filter_map(|allowed| allowed.as_str()).collect(),
+        _ => return true,
+    };
+    allowed.iter().any(|allowed| match *allowed {
+        "integer" => instance.is_i64() || instance.is_u64(),
+        "number" => instance.is_number(),
+        allowed => allowed == json_type(instance),
+    })
+}
+
+fn type_names(schema: &Value) -> String {
+    match schema.get("type") {
+        Some(Value::Array(allowed)) => allowed.iter()
+            .filter_map(|allowed| allowed.as_str())
+            .collect::<Vec<&str>>()
+            .join(" or "),
+        Some(allowed) => allowed.as_str().unwrap_or_default().to_string(),
+        None => String::from("anything"),
+    }
+}
+
+fn json_type(instance: &Value) -> &'static str {
+    match instance {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn escape_pointer(field: &str) -> String {
+    field.replace('~', "~0").replace('/', "~1")
+}
+
+// The line of a pointer, or of the closest parent found (a missing field is on its record's line).
+fn pointer_line(lines: &HashMap<String, usize>, pointer: &str) -> usize {
+    let mut pointer = pointer;
+    loop {
+        if let Some(line) = lines.get(pointer) {
+            return *line;
+        }
+        match pointer.rfind('/') {
+            Some(parent_end) => pointer = &pointer[..parent_end],
+            None => return 1,
+        }
+    }
+}
+
+// Walk JSON text (already known to parse) and note the line every value starts on by its pointer.
+fn pointer_lines(text: &str) -> HashMap<String, usize> {
+    let mut scanner = Scanner { bytes: text.as_bytes(), position: 0, line: 1 };
+    let mut lines = HashMap::new();
+    scanner.value(String::new(), &mut lines);
+    lines
+}
+
+struct Scanner<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    line: usize,
+}
+
+impl Scanner<'_> {
+    fn value(&mut self, pointer: String, lines: &mut HashMap<String, usize>) {
+        self.skip_whitespace();
+        lines.insert(pointer.clone(), self.line);
+        match self.bytes.get(self.position) {
+            Some(b'{') => {
+                self.position += 1;
+                loop {
+                    self.skip_separators();
+                    match self.bytes.get(self.position) {
+                        Some(b'}') | None => break,
+                        _ => {}
+                    }
+                    let field = self.string();
+                    self.skip_separators();
+                    self.value(format!("{}/{}", pointer, escape_pointer(&field)), lines);
+                }
+                self.position += 1;
+            }
+            Some(b'[') => {
+                self.position += 1;
+                let mut index = 0;
+                loop {
+                    self.skip_separators();
+                    match self.bytes.get(self.position) {
+                        Some(b']') | None => break,
+                        _ => {}
+                    }
+                    self.value(format!("{}/{}", pointer, index), lines);
+                    index += 1;
+                }
+                self.position += 1;
+            }
+            Some(b'"') => {
+                self.string();
+            }
+            _ => {
+                while let Some(byte) = self.bytes.get(self.position) {
+                    if b",]} \t\r\n".contains(byte) {
+                        break;
+                    }
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    fn string(&mut self) -> String {
+        let start = self.position;
+        self.position += 1;
+        while let Some(byte) = self.bytes.get(self.position) {
+            self.position += 1;
+            match byte {
+                b'\\' => self.position += 1,
+                b'"' => break,
+                _ => {}
+            }
+        }
+        let raw = String::from_utf8_lossy(&self.bytes[start..self.position.min(self.bytes.len())]);
+        serde_json::from_str(&raw).unwrap_or_default()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(byte) = self.bytes.get(self.position) {
+            match byte {
+                b'\n' => self.line += 1,
+                b' ' | b'\t' | b'\r' => {}
+                _ => break,
+            }
+            self.position += 1;
+        }
+    }
+
+    // Whitespace plus the commas and colons between values
+    fn skip_separators(&mut self) {
+        loop {
+            self.skip_whitespace();
+            match self.bytes.get(self.position) {
+                Some(b',') | Some(b':') => self.position += 1,
+                _ => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,217 @@
+// WordPress export files.  Posts come from a WordPress eXtended RSS (WXR) file made by Tools ->
+// Export, the blogroll isn't part of that file but WordPress publishes it as OPML at
+// /wp-links-opml.php.
+
+use chrono::{DateTime, NaiveDateTime};
+use std::collections::HashMap;
+use xml::reader::{EventReader, XmlEvent};
+use crate::markdown::parse_date;
+
+/// A post from a WXR file, not yet saved.
+#[derive(Debug)]
+pub struct WxrPost {
+    pub title: String,
+    pub body: String,
+    pub summary: String,
+    pub tags: String,
+    pub slug: String,
+    pub time: Option<NaiveDateTime>,
+    pub status: String,
+}
+
+/// An item from a WXR file that isn't a post, with the reason it was left out.
+#[derive(Debug)]
+pub struct WxrSkipped {
+    pub title: String,
+    pub reason: String,
+}
+
+/// A blogroll link from a WordPress OPML file, not yet saved.
+#[derive(Debug)]
+pub struct WxrLink {
+    pub text: String,
+    pub title: String,
+    pub url: String,
+    pub tags: String,
+}
+
+/// Parse the posts out of a WordPress eXtended RSS file.
+///
+/// Every item with a post type of "post" becomes a WxrPost, keeping its content, excerpt (as the
+/// summary), categories and tags (as tags), slug, status and original publish date (UTC).  Pages,
+/// attachments, menu items and the rest are returned as skipped with the reason.
+///
+/// ```
+/// use nautilus::wxr::parse_wxr;
+///
+/// let wxr = r#"<rss xmlns:content="http://purl.org/rss/1.0/modules/content/"
+///   xmlns:excerpt="http://wordpress.org/export/1.2/excerpt/"
+///   xmlns:wp="http://wordpress.org/export/1.2/"><channel><item>
+///   <title>Hello</title>
+///   <content:encoded><![CDATA[<p>Hi there</p>]]></content:encoded>
+///   <excerpt:encoded><![CDATA[Hi]]></excerpt:encoded>
+///   <wp:post_date_gmt>2020-04-24 21:09:03</wp:post_date_gmt>
+///   <wp:post_name>hello</wp:post_name>
+///   <wp:status>publish</wp:status>
+///   <wp:post_type>post</wp:post_type>
+///   <category domain="category" nicename="rust"><![CDATA[Rust]]></category>
+///   <category domain="post_tag" nicename="cli"><![CDATA[cli]]></category>
+/// </item></channel></rss>"#;
+///
+/// let (posts, skipped) = parse_wxr(wxr).unwrap();
+/// assert_eq!(posts[0].title, "Hello");
+/// assert_eq!(posts[0].tags, "Rust, cli");
+/// assert!(skipped.is_empty());
+/// ```
+pub fn parse_wxr(contents: &str) -> Result<(Vec<WxrPost>, Vec<WxrSkipped>), String> {
+    let mut posts = Vec::new();
+    let mut skipped = Vec::new();
+
+    let mut path: Vec<String> = Vec::new();
+    let mut fields: HashMap<String, String> = HashMap::new();
+    let mut categories: Vec<String> = Vec::new();
+    let mut text = String::new();
+
+    for event in EventReader::from_str(contents) {
+        match event.map_err(|e| format!("bad WXR file: {}", e))? {
+            XmlEvent::StartElement { name, .. } => {
+                let element = qualified_name(&name);
+                if element == "item" {
+                    fields.clear();
+                    categories.clear();
+                }
+                path.push(element);
+                text.clear();
+            }
+            XmlEvent::Characters(characters) | XmlEvent::CData(characters) => {
+                text.push_str(&characters);
+            }
+            XmlEvent::EndElement { .. } => {
+                let element = path.pop().unwrap_or_default();
+                let in_item = path.last().map(|parent| parent == "item").unwrap_or(false);
+                if element == "item" {
+                    match item_to_post(&fields, &categories) {
+                        Ok(post) => posts.push(post),
+                        Err(reason) => skipped.push(WxrSkipped {
+                            title: fields.get("title").cloned().unwrap_or_default(),
+                            reason,
+                        }),
+                    }
+                } else if in_item && element == "category" {
+                    if !categories.contains(&text.trim().to_string()) {
+                        categories.push(text.trim().to_string());
+                    }
+                } else if in_item {
+                    fields.insert(element, text.clone());
+                }
+                text.clear();
+            }
+            _ => {}
+        }
+    }
+
+    Ok((posts, skipped))
+}
+
+/// Parse the blogroll links out of a WordPress links OPML file (from /wp-links-opml.php).
+///
+/// Links are grouped in category outlines, the category names become the link tags.
+///
+/// ```
+/// use nautilus::wxr::parse_blogroll;
+///
+/// let opml = r#"<opml version="1.0"><body>
+///   <outline type="category" title="Friends">
+///     <outline text="Rust" type="link" htmlUrl="https://www.rust-lang.org/" title="Rust lang" />
+///   </outline>
+/// </body></opml>"#;
+///
+/// let links = parse_blogroll(opml).unwrap();
+/// assert_eq!(links[0].url, "https://www.rust-lang.org/");
+/// assert_eq!(links[0].tags, "Friends");
+/// ```
+pub fn parse_blogroll(contents: &str) -> Result<Vec<WxrLink>, String> {
+    let mut links = Vec::new();
+    let mut categories: Vec<Option<String>> = Vec::new();
+
+    for event in EventReader::from_str(contents) {
+        match event.map_err(|e| format!("bad OPML file: {}", e))? {
+            XmlEvent::StartElement { name, attributes, .. } => {
+                if name.local_name != "outline" {
+                    continue;
+                }
+                let attribute = |wanted: &str| attributes.iter()
+                    .find(|attribute| attribute.name.local_name == wanted)
+                    .map(|attribute| attribute.value.trim().to_string())
+                    .unwrap_or_default();
+                let url = attribute("htmlUrl");
+                if url.is_empty() {
+                    let category = if attribute("title").is_empty() { attribute("text") } else { attribute("title") };
+                    categories.push(Some(category));
+                } else {
+                    let text = attribute("text");
+                    let title = if attribute("title").is_empty() { text.clone() } else { attribute("title") };
+                    let tags: Vec<String> = categories.iter()
+                        .filter_map(|category| category.clone())
+                        .filter(|category| !category.is_empty())
+                        .collect();
+                    links.push(WxrLink { text, title, url, tags: tags.join(", ") });
+                    categories.push(None);
+                }
+            }
+            XmlEvent::EndElement { name } => {
+                if name.local_name == "outline" {
+                    categories.pop();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(links)
+}
+
+fn qualified_name(name: &xml::name::OwnedName) -> String {
+    match &name.prefix {
+        Some(prefix) => format!("{}:{}", prefix, name.local_name),
+        None => name.local_name.clone(),
+    }
+}
+
+fn item_to_post(fields: &HashMap<String, String>, categories: &[String]) -> Result<WxrPost, String> {
+    let field = |name: &str| fields.get(name).map(|value| value.trim().to_string()).unwrap_or_default();
+
+    let post_type = field("wp:post_type");
+    if post_type != "post" {
+        return Err(format!("post type {}, not a post", post_type));
+    }
+    let title = field("title");
+    if title.is_empty() {
+        return Err(String::from("post has no title"));
+    }
+
+    // Drafts have a GMT date of all zeros, fall back to the local date and then the RSS date
+    let time = parse_date(&field("wp:post_date_gmt"))
+        .or_else(|| parse_date(&field("wp:post_date")))
+        .or_else(|| DateTime::parse_from_rfc2822(&field("pubDate")).ok().map(|date| date.naive_utc()));
+
+    let slug = if field("wp:post_name").is_empty() {
+        crate::slugify(&title)
+    } else {
+        field("wp:post_name")
+    };
+
+    Ok(WxrPost {
+        title,
+        body: fields.get("content:encoded").cloned().unwrap_or_default(),
+        summary: field("excerpt:encoded"),
+        tags: categories.iter()
+            .filter(|category| !category.is_empty())
+            .cloned()
+            .collect::<Vec<String>>()
+            .join(", "),
+        slug,
+        time,
+        status: field("wp:status"),
+    })
+}